@@ -4,8 +4,9 @@
 #![allow(clippy::needless_question_mark)]
 #![allow(clippy::needless_borrows_for_generic_args)]
 
-use mlua::prelude::*;
+use mlua::{Buffer as LuaBuffer, prelude::*};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use lune_utils::TableBuilder;
@@ -29,11 +30,40 @@ const TYPE_F32: u8 = 9;
 const TYPE_F64: u8 = 10;
 const TYPE_BOOL: u8 = 11;
 const TYPE_STRING: u8 = 12;
+const TYPE_CSTRING: u8 = 13;
+// Milliseconds since the unix epoch as an i64, same as BSON / mongo DateTime
+const TYPE_DATETIME: u8 = 14;
+// Decimal stored exactly as an i64 mantissa followed by a u8 scale, worth mantissa / 10^scale
+const TYPE_FIXED: u8 = 15;
+
+// Largest scale for fixed decimals, since 10^19 no longer fits in an i64
+const FIXED_MAX_SCALE: u8 = 18;
+
+// Unchanged runs shorter than an edit header are cheaper to resend than to skip
+const PATCH_MERGE_GAP: usize = 8;
+
+// Serialized files start with this magic, followed by a flags byte, files without it
+// are from before the header was added and always use little-endian byte order
+const SERIALIZE_MAGIC: &[u8; 4] = b"LUNF";
+const SERIALIZE_FLAG_BIG_ENDIAN: u8 = 0b0000_0001;
+
+// 32-bit FNV-1a, so that hashes fit exactly in a Lua number
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+// Log records are framed by their length and FNV-1a checksum as little-endian u32s,
+// no matter the endianness of the file, so that logs can always be scanned the same way
+const RECORD_HEADER_LEN: usize = 8;
 
 #[derive(Clone)]
-struct FileObject {
+pub struct FileObject {
     raw_region: Arc<Mutex<Vec<u8>>>,
     safe_region: Arc<Mutex<HashMap<u32, Vec<u8>>>>,
+    readonly: Arc<AtomicBool>,
+    // Largest length the raw region may grow to, usize::MAX when unlimited
+    max_size: Arc<AtomicUsize>,
+    // Byte order for multi-byte values in the raw region, little-endian by default
+    big_endian: Arc<AtomicBool>,
 }
 
 impl FileObject {
@@ -41,98 +71,253 @@ impl FileObject {
         Self {
             raw_region: Arc::new(Mutex::new(Vec::new())),
             safe_region: Arc::new(Mutex::new(HashMap::new())),
+            readonly: Arc::new(AtomicBool::new(false)),
+            max_size: Arc::new(AtomicUsize::new(usize::MAX)),
+            big_endian: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    fn write_raw(&self, lua: &Lua, pos: usize, type_id: u8, value: LuaValue) -> LuaResult<()> {
-        let mut raw = self.raw_region.lock().unwrap();
+    fn ensure_writable(&self) -> LuaResult<()> {
+        if self.readonly.load(Ordering::Acquire) {
+            return Err(LuaError::external("FileObject is read-only"));
+        }
+        Ok(())
+    }
+
+    fn ensure_fits(&self, len: usize) -> LuaResult<()> {
+        if len > self.max_size.load(Ordering::Acquire) {
+            return Err(LuaError::external(
+                "Write would grow FileObject past its maximum size",
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_raw(&self, lua: &Lua, pos: usize, type_id: u8, value: LuaValue) -> LuaResult<usize> {
+        self.ensure_writable()?;
         let mut bytes = Vec::new();
+        let big_endian = self.big_endian.load(Ordering::Acquire);
+        Self::encode_raw(lua, type_id, value, big_endian, &mut bytes)?;
+        self.write_bytes(pos, &bytes)
+    }
+
+    fn encode_raw(
+        lua: &Lua,
+        type_id: u8,
+        value: LuaValue,
+        big_endian: bool,
+        bytes: &mut Vec<u8>,
+    ) -> LuaResult<()> {
+        macro_rules! put {
+            ($value:expr) => {{
+                let value = $value;
+                if big_endian {
+                    bytes.extend_from_slice(&value.to_be_bytes());
+                } else {
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }};
+        }
 
         match type_id {
             TYPE_I8 => bytes.push(lua.unpack::<i8>(value)? as u8),
             TYPE_U8 => bytes.push(lua.unpack::<u8>(value)?),
-            TYPE_I16 => bytes.extend_from_slice(&lua.unpack::<i16>(value)?.to_le_bytes()),
-            TYPE_U16 => bytes.extend_from_slice(&lua.unpack::<u16>(value)?.to_le_bytes()),
-            TYPE_I32 => bytes.extend_from_slice(&lua.unpack::<i32>(value)?.to_le_bytes()),
-            TYPE_U32 => bytes.extend_from_slice(&lua.unpack::<u32>(value)?.to_le_bytes()),
-            TYPE_I64 => bytes.extend_from_slice(&lua.unpack::<i64>(value)?.to_le_bytes()),
-            TYPE_U64 => bytes.extend_from_slice(&lua.unpack::<u64>(value)?.to_le_bytes()),
-            TYPE_F32 => bytes.extend_from_slice(&lua.unpack::<f32>(value)?.to_le_bytes()),
-            TYPE_F64 => bytes.extend_from_slice(&lua.unpack::<f64>(value)?.to_le_bytes()),
+            TYPE_I16 => put!(lua.unpack::<i16>(value)?),
+            TYPE_U16 => put!(lua.unpack::<u16>(value)?),
+            TYPE_I32 => put!(lua.unpack::<i32>(value)?),
+            TYPE_U32 => put!(lua.unpack::<u32>(value)?),
+            TYPE_I64 | TYPE_DATETIME => put!(lua.unpack::<i64>(value)?),
+            TYPE_U64 => put!(lua.unpack::<u64>(value)?),
+            TYPE_F32 => put!(lua.unpack::<f32>(value)?),
+            TYPE_F64 => put!(lua.unpack::<f64>(value)?),
             TYPE_BOOL => bytes.push(u8::from(lua.unpack::<bool>(value)?)),
+            TYPE_FIXED => {
+                let fixed: FileFixed = lua.unpack(value)?;
+                put!(fixed.mantissa);
+                bytes.push(fixed.scale);
+            }
             TYPE_STRING => {
                 let s: LuaString = lua.unpack(value)?;
                 let b = s.as_bytes();
-                let len = b.len() as u32;
-                bytes.extend_from_slice(&len.to_le_bytes());
+                put!(b.len() as u32);
+                bytes.extend_from_slice(b.as_ref());
+            }
+            TYPE_CSTRING => {
+                let s: LuaString = lua.unpack(value)?;
+                let b = s.as_bytes();
+                if b.contains(&0) {
+                    return Err(LuaError::external("C string cannot contain NUL bytes"));
+                }
                 bytes.extend_from_slice(b.as_ref());
+                bytes.push(0);
             }
             _ => return Err(LuaError::external("Invalid type id")),
         }
 
-        if raw.len() < pos + bytes.len() {
-            raw.resize(pos + bytes.len(), 0);
+        Ok(())
+    }
+
+    fn write_bytes(&self, pos: usize, bytes: &[u8]) -> LuaResult<usize> {
+        let mut raw = self.raw_region.lock().unwrap();
+        let end = pos
+            .checked_add(bytes.len())
+            .ok_or_else(|| LuaError::external("Write position out of range"))?;
+
+        if raw.len() < end {
+            self.ensure_fits(end)?;
+            raw.resize(end, 0);
+        }
+
+        raw[pos..end].copy_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    /**
+        Writes the values listed by `format` back to back, without any type information.
+
+        Everything is encoded before the raw region is touched, so a value
+        that does not fit its format leaves the file unchanged.
+    */
+    fn pack(&self, lua: &Lua, pos: usize, format: &str, values: LuaMultiValue) -> LuaResult<usize> {
+        self.ensure_writable()?;
+        let type_ids = parse_pack_format(format)?;
+        if values.len() < type_ids.len() {
+            return Err(LuaError::external(format!(
+                "Format '{format}' expects {} values, got {}",
+                type_ids.len(),
+                values.len()
+            )));
         }
 
-        raw[pos..pos + bytes.len()].copy_from_slice(&bytes);
-        Ok(())
+        let mut bytes = Vec::new();
+        let big_endian = self.big_endian.load(Ordering::Acquire);
+        for (type_id, value) in type_ids.into_iter().zip(values) {
+            Self::encode_raw(lua, type_id, value, big_endian, &mut bytes)?;
+        }
+
+        Ok(pos + self.write_bytes(pos, &bytes)?)
+    }
+
+    fn unpack(&self, lua: &Lua, mut pos: usize, format: &str) -> LuaResult<LuaMultiValue> {
+        let type_ids = parse_pack_format(format)?;
+        let raw = self.raw_region.lock().unwrap();
+        let big_endian = self.big_endian.load(Ordering::Acquire);
+
+        let mut values = LuaMultiValue::with_capacity(type_ids.len() + 1);
+        for type_id in type_ids {
+            let value = Self::decode_raw(lua, &raw, pos, type_id, big_endian)?;
+            if value.is_nil() {
+                return Err(LuaError::external(format!(
+                    "Not enough bytes to unpack format '{format}' at position {pos}"
+                )));
+            }
+            values.push_back(value);
+            pos += Self::min_len(type_id);
+        }
+
+        // Same as string.unpack, the position after the last value comes last
+        values.push_back(LuaValue::Integer(pos as i64));
+        Ok(values)
     }
 
     fn read_raw(&self, lua: &Lua, pos: usize, type_id: u8) -> LuaResult<LuaValue> {
         let raw = self.raw_region.lock().unwrap();
+        let big_endian = self.big_endian.load(Ordering::Acquire);
+        Self::decode_raw(lua, &raw, pos, type_id, big_endian)
+    }
 
-        if pos >= raw.len() {
+    /**
+        Copies the string at `pos` from the raw region straight into `buffer` at `offset`,
+        without creating a Lua string for it, returning its length. Strings running
+        past the end of the raw region read as nil, the same as `read_raw`.
+    */
+    fn read_string_into(
+        &self,
+        pos: usize,
+        buffer: &LuaBuffer,
+        offset: usize,
+    ) -> LuaResult<Option<usize>> {
+        let raw = self.raw_region.lock().unwrap();
+        let big_endian = self.big_endian.load(Ordering::Acquire);
+
+        let Some(len_bytes) = pos.checked_add(4).and_then(|end| raw.get(pos..end)) else {
+            return Ok(None);
+        };
+        let len_bytes: [u8; 4] = len_bytes.try_into().unwrap();
+        let len = if big_endian {
+            u32::from_be_bytes(len_bytes)
+        } else {
+            u32::from_le_bytes(len_bytes)
+        } as usize;
+
+        let Some(data) = raw.get(pos + 4..pos + 4 + len) else {
+            return Ok(None);
+        };
+        if offset.checked_add(len).is_none_or(|end| end > buffer.len()) {
+            return Err(LuaError::external(format!(
+                "Buffer of length {} is too small for a string of length {len} at offset {offset}",
+                buffer.len()
+            )));
+        }
+
+        buffer.write_bytes(offset, data);
+        Ok(Some(len))
+    }
+
+    fn read_sized(&self, lua: &Lua, pos: usize, type_id: u8) -> LuaResult<(LuaValue, usize)> {
+        let value = self.read_raw(lua, pos, type_id)?;
+        // Nothing was read at the end of the region, so there is nothing to advance past
+        let size = if value.is_nil() {
+            0
+        } else {
+            Self::read_len(type_id, &value)
+        };
+        Ok((value, size))
+    }
+
+    fn decode_raw(
+        lua: &Lua,
+        raw: &[u8],
+        pos: usize,
+        type_id: u8,
+        big_endian: bool,
+    ) -> LuaResult<LuaValue> {
+        // Values that run off the end of the region read as nil, instead of panicking
+        if pos >= raw.len() || pos + Self::min_len(type_id) > raw.len() {
             return Ok(LuaValue::Nil);
         }
 
+        macro_rules! get {
+            ($ty:ty) => {{
+                const LEN: usize = std::mem::size_of::<$ty>();
+                let mut arr = [0u8; LEN];
+                arr.copy_from_slice(&raw[pos..pos + LEN]);
+                if big_endian {
+                    <$ty>::from_be_bytes(arr)
+                } else {
+                    <$ty>::from_le_bytes(arr)
+                }
+            }};
+        }
+
         let value = match type_id {
             TYPE_I8 => LuaValue::Integer(raw[pos] as i8 as i64),
             TYPE_U8 => LuaValue::Integer(raw[pos] as i64),
-            TYPE_I16 => {
-                let mut arr = [0u8; 2];
-                arr.copy_from_slice(&raw[pos..pos + 2]);
-                LuaValue::Integer(i16::from_le_bytes(arr) as i64)
-            }
-            TYPE_U16 => {
-                let mut arr = [0u8; 2];
-                arr.copy_from_slice(&raw[pos..pos + 2]);
-                LuaValue::Integer(u16::from_le_bytes(arr) as i64)
-            }
-            TYPE_I32 => {
-                let mut arr = [0u8; 4];
-                arr.copy_from_slice(&raw[pos..pos + 4]);
-                LuaValue::Integer(i32::from_le_bytes(arr) as i64)
-            }
-            TYPE_U32 => {
-                let mut arr = [0u8; 4];
-                arr.copy_from_slice(&raw[pos..pos + 4]);
-                LuaValue::Integer(u32::from_le_bytes(arr) as i64)
-            }
-            TYPE_I64 => {
-                let mut arr = [0u8; 8];
-                arr.copy_from_slice(&raw[pos..pos + 8]);
-                LuaValue::Integer(i64::from_le_bytes(arr))
-            }
-            TYPE_U64 => {
-                let mut arr = [0u8; 8];
-                arr.copy_from_slice(&raw[pos..pos + 8]);
-                LuaValue::Integer(u64::from_le_bytes(arr) as i64)
-            }
-            TYPE_F32 => {
-                let mut arr = [0u8; 4];
-                arr.copy_from_slice(&raw[pos..pos + 4]);
-                LuaValue::Number(f32::from_le_bytes(arr) as f64)
-            }
-            TYPE_F64 => {
-                let mut arr = [0u8; 8];
-                arr.copy_from_slice(&raw[pos..pos + 8]);
-                LuaValue::Number(f64::from_le_bytes(arr))
-            }
+            TYPE_I16 => LuaValue::Integer(get!(i16) as i64),
+            TYPE_U16 => LuaValue::Integer(get!(u16) as i64),
+            TYPE_I32 => LuaValue::Integer(get!(i32) as i64),
+            TYPE_U32 => LuaValue::Integer(get!(u32) as i64),
+            TYPE_I64 | TYPE_DATETIME => LuaValue::Integer(get!(i64)),
+            TYPE_U64 => LuaValue::Integer(get!(u64) as i64),
+            TYPE_F32 => LuaValue::Number(get!(f32) as f64),
+            TYPE_F64 => LuaValue::Number(get!(f64)),
             TYPE_BOOL => LuaValue::Boolean(raw[pos] == 1),
+            TYPE_FIXED => {
+                let fixed = FileFixed::new(get!(i64), raw[pos + 8])?;
+                LuaValue::UserData(lua.create_userdata(fixed)?)
+            }
             TYPE_STRING => {
-                let mut len_arr = [0u8; 4];
-                len_arr.copy_from_slice(&raw[pos..pos + 4]);
-                let len = u32::from_le_bytes(len_arr) as usize;
+                let len = get!(u32) as usize;
                 let start = pos + 4;
                 let end = start + len;
                 if end > raw.len() {
@@ -141,13 +326,42 @@ impl FileObject {
                 let data = &raw[start..end];
                 LuaValue::String(lua.create_string(data)?)
             }
+            TYPE_CSTRING => {
+                let len = raw[pos..]
+                    .iter()
+                    .position(|&b| b == 0)
+                    .ok_or_else(|| LuaError::external("Missing NUL terminator for C string"))?;
+                LuaValue::String(lua.create_string(&raw[pos..pos + len])?)
+            }
             _ => return Err(LuaError::external("Invalid type id")),
         };
 
         Ok(value)
     }
 
+    // Smallest number of bytes a value of the given type can take up in the raw region
+    fn min_len(type_id: u8) -> usize {
+        match type_id {
+            TYPE_I8 | TYPE_U8 | TYPE_BOOL | TYPE_CSTRING => 1,
+            TYPE_I16 | TYPE_U16 => 2,
+            TYPE_I32 | TYPE_U32 | TYPE_F32 | TYPE_STRING => 4,
+            TYPE_I64 | TYPE_U64 | TYPE_F64 | TYPE_DATETIME => 8,
+            TYPE_FIXED => 9,
+            _ => 0,
+        }
+    }
+
+    // Number of bytes a value read using read_raw took up in the raw region
+    fn read_len(type_id: u8, value: &LuaValue) -> usize {
+        match (type_id, value) {
+            (TYPE_STRING, LuaValue::String(s)) => 4 + s.as_bytes().len(),
+            (TYPE_CSTRING, LuaValue::String(s)) => s.as_bytes().len() + 1,
+            _ => Self::min_len(type_id),
+        }
+    }
+
     fn safe_write(&self, lua: &Lua, slot: u32, value: LuaValue) -> LuaResult<()> {
+        self.ensure_writable()?;
         let mut safe = self.safe_region.lock().unwrap();
         let mut bytes = Vec::new();
         Self::encode_safe_value(lua, value, &mut bytes)?;
@@ -155,6 +369,83 @@ impl FileObject {
         Ok(())
     }
 
+    fn safe_cas(&self, lua: &Lua, slot: u32, expected: LuaValue, new: LuaValue) -> LuaResult<bool> {
+        self.ensure_writable()?;
+
+        let mut expected_bytes = Vec::new();
+        Self::encode_safe_value(lua, expected, &mut expected_bytes)?;
+        let mut new_bytes = Vec::new();
+        Self::encode_safe_value(lua, new, &mut new_bytes)?;
+
+        // Held across the compare and the write, so that no other write can land in between
+        let mut safe = self.safe_region.lock().unwrap();
+
+        // Empty slots read as nil, so they compare equal to an expected nil
+        let current = safe.get(&slot).map_or(&[0u8][..], Vec::as_slice);
+        if current != expected_bytes.as_slice() {
+            return Ok(false);
+        }
+
+        safe.insert(slot, new_bytes);
+        Ok(true)
+    }
+
+    fn reader(&self) -> BufferReader {
+        let raw = self.raw_region.lock().unwrap();
+        BufferReader {
+            bytes: Arc::from(raw.as_slice()),
+            pos: 0,
+            big_endian: self.big_endian.load(Ordering::Acquire),
+        }
+    }
+
+    fn set_endianness(&self, endianness: &str) -> LuaResult<()> {
+        let big_endian = match endianness {
+            "little" => false,
+            "big" => true,
+            _ => {
+                return Err(LuaError::external(format!(
+                    "Invalid endianness '{endianness}', expected 'little' or 'big'"
+                )));
+            }
+        };
+        self.big_endian.store(big_endian, Ordering::Release);
+        Ok(())
+    }
+
+    fn merge_safe(&self, other: &FileObject, policy: &str) -> LuaResult<()> {
+        self.ensure_writable()?;
+
+        // Copied out first, so that merging a file into itself does not deadlock
+        let incoming = other.safe_region.lock().unwrap().clone();
+        let mut safe = self.safe_region.lock().unwrap();
+
+        match policy {
+            "overwrite" => safe.extend(incoming),
+            "keep" => {
+                for (slot, bytes) in incoming {
+                    safe.entry(slot).or_insert(bytes);
+                }
+            }
+            "error" => {
+                // Checked up front, so that a collision leaves this file untouched
+                if let Some(slot) = incoming.keys().filter(|slot| safe.contains_key(slot)).min() {
+                    return Err(LuaError::external(format!(
+                        "Safe slot {slot} exists in both FileObjects"
+                    )));
+                }
+                safe.extend(incoming);
+            }
+            _ => {
+                return Err(LuaError::external(format!(
+                    "Invalid merge policy '{policy}', expected 'overwrite', 'keep' or 'error'"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
     fn safe_read(&self, lua: &Lua, slot: u32) -> LuaResult<LuaValue> {
         let safe = self.safe_region.lock().unwrap();
         if let Some(bytes) = safe.get(&slot) {
@@ -225,16 +516,261 @@ impl FileObject {
         }
     }
 
-    fn serialize(&self) -> Vec<u8> {
+    fn diff(&self, other: &FileObject) -> Vec<u8> {
+        let old = self.raw_region.lock().unwrap().clone();
+        let new = other.raw_region.lock().unwrap();
+
+        // Bytes past the end of the old region are always included, since
+        // a patch can only grow a region by as many bytes as it carries
+        let differs = |i: usize| old.get(i).is_none_or(|byte| *byte != new[i]);
+
+        let mut edits: Vec<(usize, usize)> = Vec::new();
+        let mut i = 0;
+
+        while i < new.len() {
+            if !differs(i) {
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            while i < new.len() && differs(i) {
+                i += 1;
+            }
+
+            match edits.last_mut() {
+                Some((_, end)) if start - *end < PATCH_MERGE_GAP => *end = i,
+                _ => edits.push((start, i)),
+            }
+        }
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&(new.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(edits.len() as u32).to_le_bytes());
+
+        for (start, end) in edits {
+            out.extend_from_slice(&(start as u32).to_le_bytes());
+            out.extend_from_slice(&((end - start) as u32).to_le_bytes());
+            out.extend_from_slice(&new[start..end]);
+        }
+
+        out
+    }
+
+    fn apply_patch(&self, patch: &[u8]) -> LuaResult<()> {
+        self.ensure_writable()?;
+
+        let read_u32 = |cursor: usize| -> LuaResult<usize> {
+            let bytes = patch
+                .get(cursor..cursor + 4)
+                .ok_or_else(|| LuaError::external("Invalid patch data"))?;
+            let mut arr = [0u8; 4];
+            arr.copy_from_slice(bytes);
+            Ok(u32::from_le_bytes(arr) as usize)
+        };
+
+        let new_len = read_u32(0)?;
+        let count = read_u32(4)?;
+        let mut cursor = 8;
+
+        // Validate all edits before touching the region, so a
+        // malformed patch never leaves it partially applied
+        let mut edits = Vec::with_capacity(count.min(patch.len() / 8));
+        let mut carried = 0;
+        for _ in 0..count {
+            let offset = read_u32(cursor)?;
+            let len = read_u32(cursor + 4)?;
+            cursor += 8;
+
+            let data = patch
+                .get(cursor..cursor + len)
+                .ok_or_else(|| LuaError::external("Invalid patch data"))?;
+            cursor += len;
+
+            if offset + len > new_len {
+                return Err(LuaError::external("Invalid patch data"));
+            }
+
+            carried += len;
+            edits.push((offset, data));
+        }
+
+        let mut raw = self.raw_region.lock().unwrap();
+        if new_len > raw.len() {
+            // Patches from diff carry every byte they add, so a patch claiming to grow
+            // the region by more than that is malformed, and should not allocate anything
+            let growth = new_len - raw.len();
+            if growth > carried {
+                return Err(LuaError::external(
+                    "Invalid patch data - grows the region by more bytes than it carries",
+                ));
+            }
+            self.ensure_fits(new_len)?;
+            raw.try_reserve(growth).map_err(|err| {
+                LuaError::external(format!("Failed to grow region by {growth} bytes - {err}"))
+            })?;
+        }
+        raw.resize(new_len, 0);
+
+        for (offset, data) in edits {
+            raw[offset..offset + data.len()].copy_from_slice(data);
+        }
+
+        Ok(())
+    }
+
+    fn hash(&self, pos: Option<usize>, len: Option<usize>) -> LuaResult<u32> {
+        let raw = self.raw_region.lock().unwrap();
+
+        let pos = pos.unwrap_or(0);
+        let len = len.unwrap_or_else(|| raw.len().saturating_sub(pos));
+
+        let bytes = pos
+            .checked_add(len)
+            .and_then(|end| raw.get(pos..end))
+            .ok_or_else(|| LuaError::external("Hash range out of bounds"))?;
+
+        Ok(fnv1a(bytes))
+    }
+
+    fn append_record(&self, data: &[u8]) -> LuaResult<usize> {
+        self.ensure_writable()?;
+
+        let len = u32::try_from(data.len())
+            .map_err(|_| LuaError::external("Record is too large to be framed"))?;
+
+        let mut raw = self.raw_region.lock().unwrap();
+        let pos = raw.len();
+        self.ensure_fits(pos + RECORD_HEADER_LEN + data.len())?;
+
+        raw.extend_from_slice(&len.to_le_bytes());
+        raw.extend_from_slice(&fnv1a(data).to_le_bytes());
+        raw.extend_from_slice(data);
+
+        Ok(pos)
+    }
+
+    fn scan_records(&self, lua: &Lua, callback: &LuaFunction, pos: usize) -> LuaResult<usize> {
+        // Scanned from a copy, so that the callback is free to use the file itself
+        let raw = self.raw_region.lock().unwrap().clone();
+
+        let mut pos = pos;
+        while let Some(header) = raw.get(pos..pos + RECORD_HEADER_LEN) {
+            let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+            let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+            // A frame that is cut short or does not match its checksum was torn
+            // while being written, and nothing after it can be trusted either
+            let start = pos + RECORD_HEADER_LEN;
+            let Some(data) = start.checked_add(len).and_then(|end| raw.get(start..end)) else {
+                break;
+            };
+            if fnv1a(data) != checksum {
+                break;
+            }
+
+            pos = start + len;
+            callback.call::<()>(lua.create_string(data)?)?;
+        }
+
+        Ok(pos)
+    }
+
+    fn view_as(&self, pos: usize, type_id: u8, count: usize) -> LuaResult<FileView> {
+        // Only fixed-size types can be indexed directly
+        let size = match type_id {
+            TYPE_STRING | TYPE_CSTRING => 0,
+            _ => Self::min_len(type_id),
+        };
+        if size == 0 {
+            return Err(LuaError::external(
+                "Views can only be created over fixed-size types",
+            ));
+        }
+
+        let len = self.raw_region.lock().unwrap().len();
+        let in_bounds = count
+            .checked_mul(size)
+            .and_then(|bytes| pos.checked_add(bytes))
+            .is_some_and(|end| end <= len);
+        if !in_bounds {
+            return Err(LuaError::external("View range out of bounds"));
+        }
+
+        Ok(FileView {
+            file: self.clone(),
+            pos,
+            type_id,
+            size,
+            count,
+        })
+    }
+
+    /**
+        Serializes the raw and safe regions, along with the endianness of the file.
+    */
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn serialize(&self) -> Vec<u8> {
         let raw = self.raw_region.lock().unwrap();
         let safe = self.safe_region.lock().unwrap();
 
-        let mut out = Vec::new();
-        out.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+        let mut out = self.serialize_header(raw.len());
         out.extend_from_slice(&raw);
+        out.extend_from_slice(&Self::serialize_safe(&safe));
+        out
+    }
+
+    /**
+        Serializes the file the same way as `serialize`, except for the raw region,
+        for writing files out without holding all of their bytes in memory twice.
+
+        Returns the bytes that come before the raw region, the length of the raw
+        region, and the bytes that come after it. The raw region itself can then
+        be copied out in chunks of any size using `copy_raw`.
+    */
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn serialize_parts(&self) -> (Vec<u8>, usize, Vec<u8>) {
+        let raw_len = self.raw_region.lock().unwrap().len();
+        let safe = Self::serialize_safe(&self.safe_region.lock().unwrap());
+        (self.serialize_header(raw_len), raw_len, safe)
+    }
+
+    /**
+        Copies bytes from the raw region starting at `offset` into `out`,
+        returning how many were copied, which is fewer than the length
+        of `out` if the raw region ends before filling it.
+    */
+    #[allow(clippy::missing_panics_doc)]
+    pub fn copy_raw(&self, offset: usize, out: &mut [u8]) -> usize {
+        let raw = self.raw_region.lock().unwrap();
+        let available = raw.get(offset..).unwrap_or_default();
+        let len = out.len().min(available.len());
+        out[..len].copy_from_slice(&available[..len]);
+        len
+    }
+
+    fn serialize_header(&self, raw_len: usize) -> Vec<u8> {
+        let mut flags = 0;
+        if self.big_endian.load(Ordering::Acquire) {
+            flags |= SERIALIZE_FLAG_BIG_ENDIAN;
+        }
+
+        // NOTE: Lengths in the serialized layout itself are always little-endian,
+        // only values in the raw region are affected by the endianness flag
+        let mut out = Vec::new();
+        out.extend_from_slice(SERIALIZE_MAGIC);
+        out.push(flags);
+        out.extend_from_slice(&(raw_len as u32).to_le_bytes());
+        out
+    }
+
+    fn serialize_safe(safe: &HashMap<u32, Vec<u8>>) -> Vec<u8> {
+        let mut out = Vec::new();
         out.extend_from_slice(&(safe.len() as u32).to_le_bytes());
 
-        for (slot, data) in safe.iter() {
+        for (slot, data) in safe {
             out.extend_from_slice(&slot.to_le_bytes());
             out.extend_from_slice(&(data.len() as u32).to_le_bytes());
             out.extend_from_slice(data);
@@ -243,60 +779,56 @@ impl FileObject {
         out
     }
 
-    fn deserialize(bytes: Vec<u8>) -> Self {
+    /**
+        Reads a file from bytes created using `serialize`.
+
+        Errors if the bytes are cut short, instead of reading past their end.
+    */
+    pub fn deserialize(bytes: &[u8]) -> LuaResult<Self> {
         let mut cursor = 0;
+        let mut flags = 0;
 
-        if bytes.len() < 4 {
-            return Self::new();
+        if bytes.len() > SERIALIZE_MAGIC.len() && bytes.starts_with(SERIALIZE_MAGIC) {
+            flags = bytes[SERIALIZE_MAGIC.len()];
+            cursor = SERIALIZE_MAGIC.len() + 1;
         }
 
-        let mut raw_len_arr = [0u8; 4];
-        raw_len_arr.copy_from_slice(&bytes[cursor..cursor + 4]);
-        cursor += 4;
+        if bytes.len() < cursor + 4 {
+            return Ok(Self::new());
+        }
 
-        let raw_len = u32::from_le_bytes(raw_len_arr) as usize;
-        let raw_region = bytes[cursor..cursor + raw_len].to_vec();
-        cursor += raw_len;
+        let raw_len = take_u32(bytes, &mut cursor)? as usize;
+        let raw_region = take_bytes(bytes, &mut cursor, raw_len)?.to_vec();
 
         let mut safe_region = HashMap::new();
 
         if cursor + 4 <= bytes.len() {
-            let mut count_arr = [0u8; 4];
-            count_arr.copy_from_slice(&bytes[cursor..cursor + 4]);
-            cursor += 4;
-            let count = u32::from_le_bytes(count_arr);
+            let count = take_u32(bytes, &mut cursor)?;
 
             for _ in 0..count {
-                let mut slot_arr = [0u8; 4];
-                slot_arr.copy_from_slice(&bytes[cursor..cursor + 4]);
-                cursor += 4;
-                let slot = u32::from_le_bytes(slot_arr);
-
-                let mut len_arr = [0u8; 4];
-                len_arr.copy_from_slice(&bytes[cursor..cursor + 4]);
-                cursor += 4;
-                let len = u32::from_le_bytes(len_arr) as usize;
-
-                let data = bytes[cursor..cursor + len].to_vec();
-                cursor += len;
-
-                safe_region.insert(slot, data);
+                let slot = take_u32(bytes, &mut cursor)?;
+                let len = take_u32(bytes, &mut cursor)? as usize;
+                safe_region.insert(slot, take_bytes(bytes, &mut cursor, len)?.to_vec());
             }
         }
 
-        Self {
+        Ok(Self {
             raw_region: Arc::new(Mutex::new(raw_region)),
             safe_region: Arc::new(Mutex::new(safe_region)),
-        }
+            readonly: Arc::new(AtomicBool::new(false)),
+            max_size: Arc::new(AtomicUsize::new(usize::MAX)),
+            big_endian: Arc::new(AtomicBool::new(flags & SERIALIZE_FLAG_BIG_ENDIAN != 0)),
+        })
     }
 }
 
 impl LuaUserData for FileObject {
+    #[allow(clippy::too_many_lines)]
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         methods.add_method(
             "write",
             |lua, this, (pos, type_id, value): (usize, u8, LuaValue)| {
-                this.write_raw(lua, pos, type_id, value)
+                this.write_raw(lua, pos, type_id, value).map(|_| ())
             },
         );
 
@@ -304,18 +836,507 @@ impl LuaUserData for FileObject {
             this.read_raw(lua, pos, type_id)
         });
 
+        methods.add_method("readSized", |lua, this, (pos, type_id): (usize, u8)| {
+            this.read_sized(lua, pos, type_id)
+        });
+
+        methods.add_method(
+            "readStringInto",
+            |_, this, (pos, buffer, offset): (usize, LuaBuffer, Option<usize>)| {
+                this.read_string_into(pos, &buffer, offset.unwrap_or(0))
+            },
+        );
+
+        methods.add_method(
+            "pack",
+            |lua, this, (pos, format, values): (usize, String, LuaMultiValue)| {
+                this.pack(lua, pos, &format, values)
+            },
+        );
+
+        methods.add_method("unpack", |lua, this, (pos, format): (usize, String)| {
+            this.unpack(lua, pos, &format)
+        });
+
+        methods.add_method(
+            "values",
+            |lua, this, (type_id, pos): (u8, Option<usize>)| {
+                let this = this.clone();
+                let mut pos = pos.unwrap_or(0);
+                lua.create_function_mut(move |lua, ()| {
+                    let value = this.read_raw(lua, pos, type_id)?;
+                    if value.is_nil() {
+                        return Ok((LuaValue::Nil, None));
+                    }
+                    let start = pos;
+                    pos += Self::read_len(type_id, &value);
+                    Ok((value, Some(start)))
+                })
+            },
+        );
+
+        methods.add_method(
+            "viewAs",
+            |_, this, (pos, type_id, count): (usize, u8, usize)| this.view_as(pos, type_id, count),
+        );
+
+        methods.add_method("reader", |_, this, ()| Ok(this.reader()));
+
         methods.add_method("safeWrite", |lua, this, (slot, value): (u32, LuaValue)| {
             this.safe_write(lua, slot, value)
         });
 
         methods.add_method("safeRead", |lua, this, slot: u32| this.safe_read(lua, slot));
 
+        methods.add_method(
+            "safeCas",
+            |lua, this, (slot, expected, new): (u32, LuaValue, LuaValue)| {
+                this.safe_cas(lua, slot, expected, new)
+            },
+        );
+
+        methods.add_method(
+            "mergeSafe",
+            |_, this, (other, policy): (LuaUserDataRef<FileObject>, String)| {
+                this.merge_safe(&other, &policy)
+            },
+        );
+
+        methods.add_method("appendRecord", |_, this, data: LuaString| {
+            this.append_record(&data.as_bytes())
+        });
+
+        methods.add_method(
+            "scanRecords",
+            |lua, this, (callback, pos): (LuaFunction, Option<usize>)| {
+                this.scan_records(lua, &callback, pos.unwrap_or(0))
+            },
+        );
+
+        methods.add_method("applyPatch", |_, this, patch: LuaString| {
+            this.apply_patch(&patch.as_bytes())
+        });
+
+        methods.add_method(
+            "hash",
+            |_, this, (pos, len): (Option<usize>, Option<usize>)| this.hash(pos, len),
+        );
+
+        methods.add_method("reserve", |_, this, bytes: usize| {
+            let mut raw = this.raw_region.lock().unwrap();
+            // Room past the maximum size could never be written to
+            this.ensure_fits(raw.len().saturating_add(bytes))?;
+            raw.try_reserve(bytes).map_err(|err| {
+                LuaError::external(format!("Failed to reserve {bytes} bytes - {err}"))
+            })
+        });
+
+        methods.add_method("setMaxSize", |_, this, bytes: Option<usize>| {
+            this.max_size
+                .store(bytes.unwrap_or(usize::MAX), Ordering::Release);
+            Ok(())
+        });
+
+        methods.add_method("setEndianness", |_, this, endianness: String| {
+            this.set_endianness(&endianness)
+        });
+
+        methods.add_method("getEndianness", |_, this, ()| {
+            Ok(if this.big_endian.load(Ordering::Acquire) {
+                "big"
+            } else {
+                "little"
+            })
+        });
+
+        methods.add_method("lock", |_, this, ()| {
+            this.readonly.store(true, Ordering::Release);
+            Ok(())
+        });
+
+        methods.add_method("unlock", |_, this, ()| {
+            this.readonly.store(false, Ordering::Release);
+            Ok(())
+        });
+
+        methods.add_method("isLocked", |_, this, ()| {
+            Ok(this.readonly.load(Ordering::Acquire))
+        });
+
         methods.add_method("serialize", |lua, this, ()| {
             Ok(lua.create_string(&this.serialize())?)
         });
     }
 }
 
+/**
+    Parses a format for `pack` and `unpack` into the type of each value, using the
+    same letters as `string.pack`: `b`/`B`, `h`/`H`, `i[n]`/`I[n]`, `l`/`L`, `f[n]` and `d`.
+*/
+fn parse_pack_format(format: &str) -> LuaResult<Vec<u8>> {
+    let mut type_ids = Vec::new();
+    let mut chars = format.chars().filter(|c| !c.is_whitespace()).peekable();
+
+    while let Some(option) = chars.next() {
+        let mut size = None;
+        if matches!(option, 'i' | 'I' | 'f') {
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                let digit = digit.to_digit(10).unwrap() as usize;
+                size = Some(size.unwrap_or(0) * 10 + digit);
+            }
+        }
+
+        let type_id = match (option, size) {
+            ('b', None) | ('i', Some(1)) => TYPE_I8,
+            ('B', None) | ('I', Some(1)) => TYPE_U8,
+            ('h', None) | ('i', Some(2)) => TYPE_I16,
+            ('H', None) | ('I', Some(2)) => TYPE_U16,
+            ('i', None | Some(4)) => TYPE_I32,
+            ('I', None | Some(4)) => TYPE_U32,
+            ('l', None) | ('i', Some(8)) => TYPE_I64,
+            ('L', None) | ('I', Some(8)) => TYPE_U64,
+            ('f', None | Some(4)) => TYPE_F32,
+            ('d', None) | ('f', Some(8)) => TYPE_F64,
+            (_, Some(size)) => {
+                return Err(LuaError::external(format!(
+                    "Invalid size {size} for format option '{option}'"
+                )));
+            }
+            (_, None) => {
+                return Err(LuaError::external(format!(
+                    "Invalid format option '{option}'"
+                )));
+            }
+        };
+        type_ids.push(type_id);
+    }
+
+    Ok(type_ids)
+}
+
+// Takes the next `len` bytes of serialized data, erroring instead of reading past the end
+fn take_bytes<'a>(bytes: &'a [u8], cursor: &mut usize, len: usize) -> LuaResult<&'a [u8]> {
+    let taken = cursor
+        .checked_add(len)
+        .and_then(|end| bytes.get(*cursor..end))
+        .ok_or_else(|| LuaError::external("Invalid serialized FileObject data"))?;
+    *cursor += len;
+    Ok(taken)
+}
+
+fn take_u32(bytes: &[u8], cursor: &mut usize) -> LuaResult<u32> {
+    let mut arr = [0u8; 4];
+    arr.copy_from_slice(take_bytes(bytes, cursor, 4)?);
+    Ok(u32::from_le_bytes(arr))
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// Reads from a snapshot of the raw region taken when the reader was
+// created, so readers never see later writes or each other's positions
+struct BufferReader {
+    bytes: Arc<[u8]>,
+    pos: usize,
+    big_endian: bool,
+}
+
+impl LuaUserData for BufferReader {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut("readTyped", |lua, this, type_id: u8| {
+            let value =
+                FileObject::decode_raw(lua, &this.bytes, this.pos, type_id, this.big_endian)?;
+            if !value.is_nil() {
+                this.pos += FileObject::read_len(type_id, &value);
+            }
+            Ok(value)
+        });
+
+        methods.add_method_mut("readBytes", |lua, this, len: usize| {
+            let Some(bytes) = this
+                .pos
+                .checked_add(len)
+                .and_then(|end| this.bytes.get(this.pos..end))
+            else {
+                return Ok(None);
+            };
+            let bytes = lua.create_string(bytes)?;
+            this.pos += len;
+            Ok(Some(bytes))
+        });
+
+        methods.add_method_mut("seek", |_, this, pos: usize| {
+            if pos > this.bytes.len() {
+                return Err(LuaError::external("Seek position out of bounds"));
+            }
+            this.pos = pos;
+            Ok(())
+        });
+
+        methods.add_method("remaining", |_, this, ()| Ok(this.bytes.len() - this.pos));
+    }
+}
+
+// A typed window into the raw region, reads and writes go straight to the file
+struct FileView {
+    file: FileObject,
+    pos: usize,
+    type_id: u8,
+    size: usize,
+    count: usize,
+}
+
+impl FileView {
+    fn element_pos(&self, index: usize) -> LuaResult<usize> {
+        if index >= self.count {
+            return Err(LuaError::external(format!(
+                "View index {index} out of bounds for length {}",
+                self.count
+            )));
+        }
+        Ok(self.pos + index * self.size)
+    }
+}
+
+impl LuaUserData for FileView {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("get", |lua, this, index: usize| {
+            this.file
+                .read_raw(lua, this.element_pos(index)?, this.type_id)
+        });
+
+        methods.add_method("set", |lua, this, (index, value): (usize, LuaValue)| {
+            this.file
+                .write_raw(lua, this.element_pos(index)?, this.type_id, value)
+                .map(|_| ())
+        });
+
+        methods.add_method("len", |_, this, ()| Ok(this.count));
+    }
+}
+
+#[derive(Clone)]
+struct FileStruct {
+    fields: Vec<(String, u8)>,
+}
+
+impl FileStruct {
+    fn write(
+        &self,
+        lua: &Lua,
+        file: &FileObject,
+        mut pos: usize,
+        record: &LuaTable,
+    ) -> LuaResult<usize> {
+        for (name, type_id) in &self.fields {
+            let value: LuaValue = record.get(name.as_str())?;
+            if value.is_nil() {
+                return Err(LuaError::external(format!(
+                    "Missing value for struct field '{name}'"
+                )));
+            }
+            pos += file.write_raw(lua, pos, *type_id, value)?;
+        }
+        Ok(pos)
+    }
+
+    fn read(&self, lua: &Lua, file: &FileObject, mut pos: usize) -> LuaResult<(LuaTable, usize)> {
+        let record = lua.create_table_with_capacity(0, self.fields.len())?;
+        for (name, type_id) in &self.fields {
+            let value = file.read_raw(lua, pos, *type_id)?;
+            if value.is_nil() {
+                return Err(LuaError::external(format!(
+                    "Struct field '{name}' is out of bounds"
+                )));
+            }
+            pos += FileObject::read_len(*type_id, &value);
+            record.set(name.as_str(), value)?;
+        }
+        Ok((record, pos))
+    }
+}
+
+impl FromLua for FileStruct {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let LuaValue::Table(tab) = value else {
+            return Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "FileStruct".to_string(),
+                message: Some("expected an array of struct fields".to_string()),
+            });
+        };
+
+        let mut fields = Vec::new();
+        for field in tab.sequence_values::<LuaTable>() {
+            let field = field?;
+            let name: String = field.get("name")?;
+            let type_id: u8 = field.get("type")?;
+            if !(TYPE_I8..=TYPE_FIXED).contains(&type_id) {
+                return Err(LuaError::external(format!(
+                    "Invalid type id for struct field '{name}'"
+                )));
+            }
+            fields.push((name, type_id));
+        }
+
+        Ok(Self { fields })
+    }
+}
+
+impl LuaUserData for FileStruct {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method(
+            "write",
+            |lua, this, (file, pos, record): (LuaUserDataRef<FileObject>, usize, LuaTable)| {
+                this.write(lua, &file, pos, &record)
+            },
+        );
+
+        methods.add_method(
+            "read",
+            |lua, this, (file, pos): (LuaUserDataRef<FileObject>, usize)| {
+                this.read(lua, &file, pos)
+            },
+        );
+    }
+}
+
+/**
+    An exact decimal value, stored as an integer mantissa scaled down by 10^scale.
+*/
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileFixed {
+    mantissa: i64,
+    scale: u8,
+}
+
+impl FileFixed {
+    fn new(mantissa: i64, scale: u8) -> LuaResult<Self> {
+        if scale > FIXED_MAX_SCALE {
+            return Err(LuaError::external(format!(
+                "Fixed decimal scale must be at most {FIXED_MAX_SCALE}, got {scale}"
+            )));
+        }
+        Ok(Self { mantissa, scale })
+    }
+
+    /**
+        Parses a decimal string such as `-12.34`, using the number of
+        digits after the decimal point as the scale unless one is given.
+    */
+    fn parse(s: &str, scale: Option<u8>) -> LuaResult<Self> {
+        let invalid = || LuaError::external(format!("Invalid fixed decimal '{s}'"));
+
+        let trimmed = s.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        let frac = match scale {
+            // Only trailing zeros may be dropped, anything else would change the value
+            Some(scale) if frac.len() > usize::from(scale) => {
+                let (kept, dropped) = frac.split_at(usize::from(scale));
+                if dropped.bytes().any(|b| b != b'0') {
+                    return Err(LuaError::external(format!(
+                        "Fixed decimal '{s}' has more than {scale} decimal places"
+                    )));
+                }
+                kept.to_string()
+            }
+            Some(scale) => format!("{frac:0<width$}", width = usize::from(scale)),
+            None => frac.to_string(),
+        };
+
+        let all = format!("{whole}{frac}");
+        if all.is_empty() || !all.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let mut mantissa = all.parse::<i64>().map_err(|_| invalid())?;
+        if negative {
+            mantissa = -mantissa;
+        }
+        let scale = u8::try_from(frac.len()).map_err(|_| invalid())?;
+        Self::new(mantissa, scale)
+    }
+
+    fn from_number(n: f64, scale: u8) -> LuaResult<Self> {
+        let scaled = (n * 10f64.powi(i32::from(scale))).round();
+        // 2^63 is exactly representable, unlike i64::MAX
+        if !scaled.is_finite() || scaled < i64::MIN as f64 || scaled >= -(i64::MIN as f64) {
+            return Err(LuaError::external(format!(
+                "Fixed decimal {n} with scale {scale} is out of range"
+            )));
+        }
+        Self::new(scaled as i64, scale)
+    }
+
+    fn to_number(self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(i32::from(self.scale))
+    }
+}
+
+impl std::fmt::Display for FileFixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = usize::from(self.scale);
+        let digits = format!("{digits:0>width$}", width = scale + 1);
+        let (whole, frac) = digits.split_at(digits.len() - scale);
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if frac.is_empty() {
+            write!(f, "{sign}{whole}")
+        } else {
+            write!(f, "{sign}{whole}.{frac}")
+        }
+    }
+}
+
+impl FromLua for FileFixed {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::UserData(ud) => Ok(*ud.borrow::<Self>()?),
+            LuaValue::String(s) => Self::parse(&s.to_str()?, None),
+            _ => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "FileFixed".to_string(),
+                message: Some("expected a fixed decimal or a decimal string".to_string()),
+            }),
+        }
+    }
+}
+
+impl LuaUserData for FileFixed {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("toNumber", |_, this, ()| Ok(this.to_number()));
+        methods.add_method("raw", |_, this, ()| Ok((this.mantissa, this.scale)));
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| Ok(this.to_string()));
+        methods.add_meta_method(LuaMetaMethod::Eq, |_, this, other: LuaAnyUserData| {
+            Ok(other.borrow::<Self>().is_ok_and(|other| *this == *other))
+        });
+    }
+}
+
+fn create_fixed(_: &Lua, (value, scale): (LuaValue, Option<u8>)) -> LuaResult<FileFixed> {
+    match value {
+        LuaValue::String(s) => FileFixed::parse(&s.to_str()?, scale),
+        LuaValue::Integer(i) => FileFixed::from_number(i as f64, scale.unwrap_or(0)),
+        LuaValue::Number(n) => {
+            let scale = scale.ok_or_else(|| {
+                LuaError::external("A scale is required to create a fixed decimal from a number")
+            })?;
+            FileFixed::from_number(n, scale)
+        }
+        _ => Err(LuaError::external(format!(
+            "Expected a number or decimal string, got {}",
+            value.type_name()
+        ))),
+    }
+}
+
 pub fn module(lua: Lua) -> LuaResult<LuaTable> {
     let types = lua.create_table()?;
 
@@ -331,12 +1352,27 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
     types.set("f64", TYPE_F64)?;
     types.set("bool", TYPE_BOOL)?;
     types.set("string", TYPE_STRING)?;
+    types.set("cstring", TYPE_CSTRING)?;
+    types.set("datetime", TYPE_DATETIME)?;
+    types.set("fixed", TYPE_FIXED)?;
+
+    // Convenience aliases for C interop
+    types.set("char", TYPE_I8)?;
+    types.set("byte", TYPE_U8)?;
 
     TableBuilder::new(lua)?
         .with_function("new", |_, ()| Ok(FileObject::new()))?
         .with_function("deserialize", |_, bytes: LuaString| {
-            Ok(FileObject::deserialize(bytes.as_bytes().as_ref().to_vec()))
+            FileObject::deserialize(&bytes.as_bytes())
         })?
+        .with_function(
+            "diff",
+            |lua, (old, new): (LuaUserDataRef<FileObject>, LuaUserDataRef<FileObject>)| {
+                Ok(lua.create_string(old.diff(&new))?)
+            },
+        )?
+        .with_function("struct", |_, schema: FileStruct| Ok(schema))?
+        .with_function("fixed", create_fixed)?
         .with_value("types", types)?
         .build_readonly()
 }
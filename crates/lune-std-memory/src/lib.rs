@@ -3,10 +3,10 @@
 #![allow(clippy::pedantic)]
 
 use std::{
-    cell::RefCell,
-    collections::HashSet,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
     mem::size_of,
-    rc::Rc,
+    rc::{Rc, Weak},
     time::{Duration, Instant},
 };
 
@@ -23,24 +23,206 @@ pub fn typedefs() -> String {
 #[derive(Clone)]
 struct MemoryBlock {
     inner: Rc<RefCell<Inner>>,
+    registry: Weak<MemoryRegistry>,
+}
+
+// Deep enough for any reasonable data, shallow enough to never overflow the stack
+const DEFAULT_MAX_DEPTH: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    Any,
+    Number,
+    String,
+    Boolean,
+    Table,
+}
+
+impl ValueKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Any => "any",
+            Self::Number => "number",
+            Self::String => "string",
+            Self::Boolean => "boolean",
+            Self::Table => "table",
+        }
+    }
+
+    fn matches(self, value: &LuaValue) -> bool {
+        matches!(
+            (self, value),
+            (Self::Any, _)
+                | (Self::Number, LuaValue::Integer(_) | LuaValue::Number(_))
+                | (Self::String, LuaValue::String(_))
+                | (Self::Boolean, LuaValue::Boolean(_))
+                | (Self::Table, LuaValue::Table(_))
+        )
+    }
+}
+
+impl FromLua for ValueKind {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let name = String::from_lua(value, lua)?;
+        [
+            Self::Any,
+            Self::Number,
+            Self::String,
+            Self::Boolean,
+            Self::Table,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+        .ok_or_else(|| {
+            LuaError::runtime(format!(
+                "Invalid block type '{name}' (expected number, string, boolean, table or any)"
+            ))
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlockMode {
+    // Writes past capacity error
+    Fixed,
+    // Writes past capacity evict the oldest values until the new one fits
+    Ring,
+}
+
+impl FromLua for BlockMode {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let name = String::from_lua(value, lua)?;
+        match name.as_str() {
+            "fixed" => Ok(Self::Fixed),
+            "ring" => Ok(Self::Ring),
+            _ => Err(LuaError::runtime(format!(
+                "Invalid block mode '{name}' (expected fixed or ring)"
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BlockOptions {
+    max_depth: usize,
+    intern: bool,
+    kind: ValueKind,
+    mode: BlockMode,
+}
+
+impl Default for BlockOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            intern: false,
+            kind: ValueKind::Any,
+            mode: BlockMode::Fixed,
+        }
+    }
+}
+
+impl FromLua for BlockOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let mut options = Self::default();
+
+        match value {
+            LuaValue::Nil => {}
+            LuaValue::Table(t) => {
+                if let Some(max_depth) = t.get::<Option<usize>>("maxDepth")? {
+                    options.max_depth = max_depth;
+                }
+                if let Some(intern) = t.get::<Option<bool>>("intern")? {
+                    options.intern = intern;
+                }
+                if let Some(kind) = t.get::<Option<ValueKind>>("type")? {
+                    options.kind = kind;
+                }
+                if let Some(mode) = t.get::<Option<BlockMode>>("mode")? {
+                    options.mode = mode;
+                }
+            }
+            _ => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "BlockOptions".to_string(),
+                    message: Some("expected a table of options or nil".to_string()),
+                });
+            }
+        }
+
+        Ok(options)
+    }
 }
 
 struct Inner {
     capacity: usize,
-    buffer: Vec<LuaValue>,
+    options: BlockOptions,
+    buffer: VecDeque<LuaValue>,
+    // Size of each value in the buffer, as measured when it was stored
+    sizes: VecDeque<usize>,
+    // Running total of the size of all values in the buffer, the sum of `sizes`
+    used: usize,
+    // Strings stored in the block, by contents, when interning is enabled
+    interned: HashMap<Vec<u8>, LuaString>,
     scheduled: Option<Instant>,
     freed: bool,
 }
 
+// Contents of a block at the time of a snapshot. Tables are copied both when taking and when
+// restoring it, so later changes to them from Lua never leak into the snapshot or its sizes
+struct MemorySnapshot {
+    block: Weak<RefCell<Inner>>,
+    buffer: VecDeque<LuaValue>,
+    sizes: VecDeque<usize>,
+    used: usize,
+    interned: HashMap<Vec<u8>, LuaString>,
+}
+
+impl LuaUserData for MemorySnapshot {}
+
+// Handle to a block that does not keep it alive, and stops handing it out once it is freed
+struct MemoryWeak {
+    block: Weak<RefCell<Inner>>,
+    registry: Weak<MemoryRegistry>,
+}
+
+impl LuaUserData for MemoryWeak {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("get", |_, this, ()| {
+            let Some(inner) = this.block.upgrade() else {
+                return Ok(None);
+            };
+            if inner.borrow().freed {
+                return Ok(None);
+            }
+            Ok(Some(MemoryBlock {
+                inner,
+                registry: this.registry.clone(),
+            }))
+        });
+    }
+}
+
+// Strings already stored in a block, and those first seen in the values being measured
+struct InternScope<'a> {
+    interned: &'a HashMap<Vec<u8>, LuaString>,
+    fresh: &'a mut HashMap<Vec<u8>, LuaString>,
+}
+
 impl MemoryBlock {
-    fn new(capacity: usize) -> Self {
+    fn new(capacity: usize, options: BlockOptions, registry: Weak<MemoryRegistry>) -> Self {
         Self {
             inner: Rc::new(RefCell::new(Inner {
                 capacity,
-                buffer: Vec::new(),
+                options,
+                buffer: VecDeque::new(),
+                sizes: VecDeque::new(),
+                used: 0,
+                interned: HashMap::new(),
                 scheduled: None,
                 freed: false,
             })),
+            registry,
         }
     }
 
@@ -52,7 +234,30 @@ impl MemoryBlock {
         }
     }
 
-    fn validate_value(value: &LuaValue) -> LuaResult<()> {
+    fn check_depth(depth: usize, max_depth: usize) -> LuaResult<()> {
+        if depth > max_depth {
+            Err(LuaError::runtime(format!(
+                "Value exceeds the maximum nesting depth of {max_depth}"
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_kind(inner: &Inner, value: &LuaValue) -> LuaResult<()> {
+        let kind = inner.options.kind;
+        if kind.matches(value) {
+            Ok(())
+        } else {
+            Err(LuaError::runtime(format!(
+                "Memory block only accepts {} values, got {}",
+                kind.name(),
+                value.type_name()
+            )))
+        }
+    }
+
+    fn validate_value(value: &LuaValue, depth: usize, max_depth: usize) -> LuaResult<()> {
         match value {
             LuaValue::Nil
             | LuaValue::Boolean(_)
@@ -67,10 +272,12 @@ impl MemoryBlock {
                     ));
                 }
 
+                Self::check_depth(depth + 1, max_depth)?;
+
                 for pair in t.clone().pairs::<LuaValue, LuaValue>() {
                     let (k, v) = pair?;
-                    Self::validate_value(&k)?;
-                    Self::validate_value(&v)?;
+                    Self::validate_value(&k, depth + 1, max_depth)?;
+                    Self::validate_value(&v, depth + 1, max_depth)?;
                 }
 
                 Ok(())
@@ -82,7 +289,90 @@ impl MemoryBlock {
         }
     }
 
-    fn value_size(value: &LuaValue, visited: &mut HashSet<usize>) -> LuaResult<usize> {
+    fn values_equal(a: &LuaValue, b: &LuaValue) -> LuaResult<bool> {
+        Ok(match (a, b) {
+            (LuaValue::Nil, LuaValue::Nil) => true,
+
+            (LuaValue::Boolean(a), LuaValue::Boolean(b)) => a == b,
+
+            (LuaValue::Integer(a), LuaValue::Integer(b)) => a == b,
+
+            (LuaValue::Number(a), LuaValue::Number(b)) => a == b,
+
+            (LuaValue::Integer(i), LuaValue::Number(n))
+            | (LuaValue::Number(n), LuaValue::Integer(i)) => *i as f64 == *n,
+
+            (LuaValue::String(a), LuaValue::String(b)) => a.as_bytes() == b.as_bytes(),
+
+            (LuaValue::Table(a), LuaValue::Table(b)) => {
+                if a.to_pointer() == b.to_pointer() {
+                    return Ok(true);
+                }
+
+                let mut count = 0;
+
+                for pair in a.clone().pairs::<LuaValue, LuaValue>() {
+                    let (k, v) = pair?;
+                    let other: LuaValue = b.raw_get(k)?;
+
+                    if !Self::values_equal(&v, &other)? {
+                        return Ok(false);
+                    }
+
+                    count += 1;
+                }
+
+                count == b.clone().pairs::<LuaValue, LuaValue>().count()
+            }
+
+            _ => false,
+        })
+    }
+
+    fn deep_copy(
+        lua: &Lua,
+        value: &LuaValue,
+        copies: &mut HashMap<usize, LuaTable>,
+    ) -> LuaResult<LuaValue> {
+        let LuaValue::Table(t) = value else {
+            return Ok(value.clone());
+        };
+
+        let ptr = t.to_pointer() as usize;
+        if let Some(copy) = copies.get(&ptr) {
+            return Ok(LuaValue::Table(copy.clone()));
+        }
+
+        let copy = lua.create_table()?;
+        copies.insert(ptr, copy.clone());
+
+        for pair in t.clone().pairs::<LuaValue, LuaValue>() {
+            let (k, v) = pair?;
+            copy.raw_set(
+                Self::deep_copy(lua, &k, copies)?,
+                Self::deep_copy(lua, &v, copies)?,
+            )?;
+        }
+
+        Ok(LuaValue::Table(copy))
+    }
+
+    // Tables shared between values stay shared between their copies
+    fn deep_copy_all(lua: &Lua, values: &VecDeque<LuaValue>) -> LuaResult<VecDeque<LuaValue>> {
+        let mut copies = HashMap::new();
+        values
+            .iter()
+            .map(|value| Self::deep_copy(lua, value, &mut copies))
+            .collect()
+    }
+
+    fn value_size(
+        value: &LuaValue,
+        visited: &mut HashSet<usize>,
+        mut strings: Option<&mut InternScope>,
+        depth: usize,
+        max_depth: usize,
+    ) -> LuaResult<usize> {
         Ok(match value {
             LuaValue::Nil => 0,
 
@@ -92,7 +382,21 @@ impl MemoryBlock {
 
             LuaValue::Number(_) => size_of::<f64>(),
 
-            LuaValue::String(s) => size_of::<LuaValue>() + s.as_bytes().len(),
+            LuaValue::String(s) => {
+                let bytes = s.as_bytes();
+
+                // Interned strings only count the first time their contents are stored
+                if let Some(scope) = strings {
+                    if scope.interned.contains_key(bytes.as_ref())
+                        || scope.fresh.contains_key(bytes.as_ref())
+                    {
+                        return Ok(0);
+                    }
+                    scope.fresh.insert(bytes.to_vec(), s.clone());
+                }
+
+                size_of::<LuaValue>() + bytes.len()
+            }
 
             LuaValue::Table(t) => {
                 let ptr = t.to_pointer() as usize;
@@ -101,34 +405,212 @@ impl MemoryBlock {
                     return Ok(0);
                 }
 
+                Self::check_depth(depth + 1, max_depth)?;
+
                 let mut total = size_of::<LuaValue>();
 
                 for pair in t.clone().pairs::<LuaValue, LuaValue>() {
                     let (k, v) = pair?;
-                    total += Self::value_size(&k, visited)?;
-                    total += Self::value_size(&v, visited)?;
+                    total += Self::value_size(
+                        &k,
+                        visited,
+                        strings.as_deref_mut(),
+                        depth + 1,
+                        max_depth,
+                    )?;
+                    total += Self::value_size(
+                        &v,
+                        visited,
+                        strings.as_deref_mut(),
+                        depth + 1,
+                        max_depth,
+                    )?;
                 }
 
                 total
             }
 
+            // Blobs are opaque bytes, stored once and never looked into
+            LuaValue::Buffer(b) => b.len(),
+
             _ => 0,
         })
     }
 
-    fn total_size(inner: &Inner) -> LuaResult<usize> {
-        let mut visited = HashSet::new();
+    /**
+        Measures a value about to be stored in the block.
+
+        Strings that are new to an interning block are added to `fresh`,
+        and must be added to the block once the value is actually stored.
+    */
+    fn entry_size(
+        inner: &Inner,
+        value: &LuaValue,
+        fresh: &mut HashMap<Vec<u8>, LuaString>,
+    ) -> LuaResult<usize> {
+        let mut scope = InternScope {
+            interned: &inner.interned,
+            fresh,
+        };
+        let strings = inner.options.intern.then_some(&mut scope);
+        Self::value_size(
+            value,
+            &mut HashSet::new(),
+            strings,
+            0,
+            inner.options.max_depth,
+        )
+    }
 
-        let mut total = 0;
-        for value in &inner.buffer {
-            total += Self::value_size(value, &mut visited)?;
+    /**
+        Evicts the oldest values in a ring block until `value` fits, returning its size.
+
+        Strings interned by evicted values may still be used by the remaining ones, which
+        were not charged for them, so an interning block measures those again after evicting.
+        Strings that are no longer interned then count towards `value`, which may evict more.
+    */
+    fn make_room(
+        inner: &mut Inner,
+        value: &LuaValue,
+        fresh: &mut HashMap<Vec<u8>, LuaString>,
+    ) -> LuaResult<usize> {
+        loop {
+            fresh.clear();
+            let size = Self::entry_size(inner, value, fresh)?;
+
+            if size > inner.capacity {
+                return Err(LuaError::runtime(
+                    "Fatal: value is larger than the memory block capacity",
+                ));
+            }
+            if inner.used + size <= inner.capacity {
+                return Ok(size);
+            }
+
+            while inner.used + size > inner.capacity {
+                let Some(evicted) = inner.sizes.pop_front() else {
+                    break;
+                };
+                inner.buffer.pop_front();
+                inner.used -= evicted;
+            }
+
+            if inner.options.intern {
+                Self::remeasure(inner)?;
+            }
         }
+    }
 
+    /**
+        Measures every value in the block again from scratch, so that tables changed
+        after being written are counted as they are now.
+
+        Interning blocks also drop the interned strings that none of the values use anymore.
+        Each string is counted towards the first value using it, even if that
+        value did not count it when written, since the one that did is gone.
+    */
+    fn remeasure(inner: &mut Inner) -> LuaResult<()> {
+        let mut interned = HashMap::new();
+        let mut sizes = VecDeque::with_capacity(inner.buffer.len());
+        let empty = HashMap::new();
         for value in &inner.buffer {
-            total += Self::value_size(value, &mut visited)?;
+            let mut scope = InternScope {
+                interned: &empty,
+                fresh: &mut interned,
+            };
+            sizes.push_back(Self::value_size(
+                value,
+                &mut HashSet::new(),
+                inner.options.intern.then_some(&mut scope),
+                0,
+                inner.options.max_depth,
+            )?);
+        }
+
+        inner.used = sizes.iter().sum();
+        inner.sizes = sizes;
+        if inner.options.intern {
+            inner.interned = interned;
+        }
+
+        Ok(())
+    }
+
+    // Bytes allocated by the block itself to hold its values, sizes and interned strings
+    fn storage_size(inner: &Inner) -> usize {
+        inner.buffer.capacity() * size_of::<LuaValue>()
+            + inner.sizes.capacity() * size_of::<usize>()
+            + inner.interned.capacity() * size_of::<(Vec<u8>, LuaString)>()
+            + inner.interned.keys().map(Vec::capacity).sum::<usize>()
+    }
+
+    /**
+        Measures every value again and releases storage that the block is no longer
+        using, returning the number of bytes of storage reclaimed, see `storage_size`.
+    */
+    fn compact(inner: &mut Inner) -> LuaResult<usize> {
+        let before = Self::storage_size(inner);
+
+        Self::remeasure(inner)?;
+        inner.buffer.shrink_to_fit();
+        inner.sizes.shrink_to_fit();
+        inner.interned.shrink_to_fit();
+
+        Ok(before.saturating_sub(Self::storage_size(inner)))
+    }
+
+    fn clear(inner: &mut Inner) {
+        inner.buffer.clear();
+        inner.sizes.clear();
+        inner.used = 0;
+        inner.interned.clear();
+        inner.freed = true;
+        inner.scheduled = None;
+    }
+
+    /**
+        Stores an already validated value at the end of the block,
+        evicting the oldest values first when the block is a ring.
+    */
+    fn store(inner: &mut Inner, value: LuaValue) -> LuaResult<()> {
+        // Only the new value is measured, so writes stay cheap no matter how full the block is
+        let mut fresh = HashMap::new();
+        let size = if inner.options.mode == BlockMode::Ring {
+            Self::make_room(inner, &value, &mut fresh)?
+        } else {
+            let size = Self::entry_size(inner, &value, &mut fresh)?;
+            if inner.used + size > inner.capacity {
+                return Err(LuaError::runtime("Fatal: memory exceeded capacity"));
+            }
+            size
+        };
+
+        inner.interned.extend(fresh);
+        let value = Self::intern(inner, value);
+        inner.buffer.push_back(value);
+        inner.sizes.push_back(size);
+        inner.used += size;
+
+        Ok(())
+    }
+
+    // Blobs are handed out as strings, so the stored bytes can never be changed
+    fn output(lua: &Lua, value: &LuaValue) -> LuaResult<LuaValue> {
+        match value {
+            LuaValue::Buffer(b) => Ok(LuaValue::String(lua.create_string(b.to_vec())?)),
+            value => Ok(value.clone()),
         }
+    }
 
-        Ok(total)
+    fn intern(inner: &Inner, value: LuaValue) -> LuaValue {
+        // Identical strings share the first stored copy
+        match value {
+            LuaValue::String(s) if inner.options.intern => {
+                let shared = inner.interned.get(s.as_bytes().as_ref()).cloned();
+                LuaValue::String(shared.unwrap_or(s))
+            }
+            value => value,
+        }
     }
 }
 
@@ -137,42 +619,215 @@ impl LuaUserData for MemoryBlock {
         methods.add_method_mut("Write", |_, this, value: LuaValue| {
             let mut inner = this.inner.borrow_mut();
             Self::check_alive(&inner)?;
-            Self::validate_value(&value)?;
+            Self::check_kind(&inner, &value)?;
+            Self::validate_value(&value, 0, inner.options.max_depth)?;
+            Self::store(&mut inner, value)
+        });
+
+        methods.add_method_mut("WriteBlob", |lua, this, bytes: LuaValue| {
+            let blob = match bytes {
+                LuaValue::String(s) => lua.create_buffer(s.as_bytes())?,
+                LuaValue::Buffer(b) => lua.create_buffer(b.to_vec())?,
+                _ => {
+                    return Err(LuaError::runtime(format!(
+                        "Blob must be a string or buffer, got {}",
+                        bytes.type_name()
+                    )));
+                }
+            };
+
+            let mut inner = this.inner.borrow_mut();
+            Self::check_alive(&inner)?;
+            let value = LuaValue::Buffer(blob);
+            Self::check_kind(&inner, &value)?;
+            Self::store(&mut inner, value)
+        });
+
+        methods.add_method("ReadBlob", |lua, this, index: usize| {
+            let inner = this.inner.borrow();
+            Self::check_alive(&inner)?;
+
+            let Some(value) = index.checked_sub(1).and_then(|i| inner.buffer.get(i)) else {
+                return Ok(None);
+            };
+
+            match value {
+                LuaValue::Buffer(b) => Ok(Some(lua.create_string(b.to_vec())?)),
+                _ => Err(LuaError::runtime(format!(
+                    "Value at index {index} is not a blob, got {}",
+                    value.type_name()
+                ))),
+            }
+        });
+
+        methods.add_method("Merge", |_, this, other: LuaUserDataRef<MemoryBlock>| {
+            // Copy the values out first so merging a block into itself works
+            let values = {
+                let other = other.inner.borrow();
+                Self::check_alive(&other)?;
+                other.buffer.clone()
+            };
+
+            let mut inner = this.inner.borrow_mut();
+            Self::check_alive(&inner)?;
 
-            inner.buffer.push(value);
+            // Ring blocks take the values one at a time, evicting the oldest ones as needed,
+            // but every value is checked before any of them are stored, the same as below
+            if inner.options.mode == BlockMode::Ring {
+                for value in &values {
+                    Self::check_kind(&inner, value)?;
+                    let size = Self::value_size(
+                        value,
+                        &mut HashSet::new(),
+                        None,
+                        0,
+                        inner.options.max_depth,
+                    )?;
+                    if size > inner.capacity {
+                        return Err(LuaError::runtime(
+                            "Fatal: value is larger than the memory block capacity",
+                        ));
+                    }
+                }
+                for value in values {
+                    Self::store(&mut inner, value)?;
+                }
+                return Ok(());
+            }
 
-            let used = Self::total_size(&inner)?;
+            // Values from the other block may be nested deeper than this block allows,
+            // or be of a type that this block does not accept
+            let mut fresh = HashMap::new();
+            let mut sizes = Vec::with_capacity(values.len());
+            for value in &values {
+                Self::check_kind(&inner, value)?;
+                sizes.push(Self::entry_size(&inner, value, &mut fresh)?);
+            }
 
+            let used = inner.used + sizes.iter().sum::<usize>();
             if used > inner.capacity {
-                inner.buffer.pop();
                 return Err(LuaError::runtime("Fatal: memory exceeded capacity"));
             }
 
+            inner.interned.extend(fresh);
+            for value in values {
+                let value = Self::intern(&inner, value);
+                inner.buffer.push_back(value);
+            }
+            inner.sizes.extend(sizes);
+            inner.used = used;
+
             Ok(())
         });
 
+        methods.add_method("Clone", |lua, this, ()| {
+            let inner = this.inner.borrow();
+            Self::check_alive(&inner)?;
+
+            let block = match this.registry.upgrade() {
+                Some(registry) => registry.allocate(inner.capacity, inner.options)?,
+                None => MemoryBlock::new(inner.capacity, inner.options, Weak::new()),
+            };
+
+            let mut cloned = block.inner.borrow_mut();
+            cloned.buffer = Self::deep_copy_all(lua, &inner.buffer)?;
+            cloned.sizes = inner.sizes.clone();
+            cloned.used = inner.used;
+            cloned.interned = inner.interned.clone();
+            drop(cloned);
+
+            Ok(block)
+        });
+
+        methods.add_method("Snapshot", |lua, this, ()| {
+            let inner = this.inner.borrow();
+            Self::check_alive(&inner)?;
+
+            Ok(MemorySnapshot {
+                block: Rc::downgrade(&this.inner),
+                buffer: Self::deep_copy_all(lua, &inner.buffer)?,
+                sizes: inner.sizes.clone(),
+                used: inner.used,
+                interned: inner.interned.clone(),
+            })
+        });
+
+        methods.add_method_mut(
+            "Restore",
+            |lua, this, snapshot: LuaUserDataRef<MemorySnapshot>| {
+                if !Weak::ptr_eq(&snapshot.block, &Rc::downgrade(&this.inner)) {
+                    return Err(LuaError::runtime(
+                        "Snapshot was taken from a different memory block",
+                    ));
+                }
+
+                let mut inner = this.inner.borrow_mut();
+                Self::check_alive(&inner)?;
+
+                if snapshot.used > inner.capacity {
+                    return Err(LuaError::runtime("Fatal: memory exceeded capacity"));
+                }
+
+                inner.buffer = Self::deep_copy_all(lua, &snapshot.buffer)?;
+                inner.sizes = snapshot.sizes.clone();
+                inner.used = snapshot.used;
+                inner.interned = snapshot.interned.clone();
+
+                Ok(())
+            },
+        );
+
         methods.add_method("Read", |lua, this, ()| {
             let inner = this.inner.borrow();
             Self::check_alive(&inner)?;
 
             match inner.buffer.len() {
                 0 => Ok(LuaValue::Nil),
-                1 => Ok(inner.buffer[0].clone()),
+                1 => Self::output(lua, &inner.buffer[0]),
                 _ => {
                     let table = lua.create_table()?;
                     for (i, value) in inner.buffer.iter().enumerate() {
-                        table.set(i + 1, value.clone())?;
+                        table.set(i + 1, Self::output(lua, value)?)?;
                     }
                     Ok(LuaValue::Table(table))
                 }
             }
         });
 
+        methods.add_method("Slice", |lua, this, (start, count): (usize, usize)| {
+            let inner = this.inner.borrow();
+            Self::check_alive(&inner)?;
+
+            let table = lua.create_table()?;
+
+            if start == 0 || start > inner.buffer.len() {
+                return Ok(table);
+            }
+
+            let values = inner.buffer.iter().skip(start - 1).take(count);
+            for (i, value) in values.enumerate() {
+                table.raw_set(i + 1, Self::output(lua, value)?)?;
+            }
+
+            Ok(table)
+        });
+
+        methods.add_method("Find", |_, this, value: LuaValue| {
+            let inner = this.inner.borrow();
+            Self::check_alive(&inner)?;
+            Self::validate_value(&value, 0, inner.options.max_depth)?;
+
+            for (i, stored) in inner.buffer.iter().enumerate() {
+                if Self::values_equal(stored, &value)? {
+                    return Ok(Some(i + 1));
+                }
+            }
+
+            Ok(None)
+        });
+
         methods.add_method_mut("Free", |_, this, ()| {
-            let mut inner = this.inner.borrow_mut();
-            inner.buffer.clear();
-            inner.freed = true;
-            inner.scheduled = None;
+            Self::clear(&mut this.inner.borrow_mut());
             Ok(())
         });
 
@@ -186,12 +841,20 @@ impl LuaUserData for MemoryBlock {
             Ok(())
         });
 
+        methods.add_method_mut("Compact", |_, this, ()| {
+            let mut inner = this.inner.borrow_mut();
+            Self::check_alive(&inner)?;
+            Self::compact(&mut inner)
+        });
+
         methods.add_method("Size", |_, this, ()| {
             let inner = this.inner.borrow();
             Self::check_alive(&inner)?;
-            Self::total_size(&inner)
+            Ok(inner.used)
         });
 
+        methods.add_method("IsFreed", |_, this, ()| Ok(this.inner.borrow().freed));
+
         methods.add_method("Capacity", |_, this, ()| {
             let inner = this.inner.borrow();
             Ok(inner.capacity)
@@ -202,13 +865,43 @@ impl LuaUserData for MemoryBlock {
 #[derive(Default)]
 struct MemoryRegistry {
     blocks: RefCell<Vec<MemoryBlock>>,
+    // Largest summed capacity of all live blocks, if any
+    limit: Cell<Option<usize>>,
 }
 
 impl MemoryRegistry {
     fn new() -> Self {
         Self {
             blocks: RefCell::new(Vec::new()),
+            limit: Cell::new(None),
+        }
+    }
+
+    // Freed blocks stay in the registry until the next Clean or collect, so they are skipped
+    fn used(&self) -> usize {
+        self.blocks
+            .borrow()
+            .iter()
+            .map(|block| block.inner.borrow())
+            .filter(|inner| !inner.freed)
+            .map(|inner| inner.capacity)
+            .sum()
+    }
+
+    fn allocate(self: &Rc<Self>, capacity: usize, options: BlockOptions) -> LuaResult<MemoryBlock> {
+        if let Some(limit) = self.limit.get() {
+            let used = self.used();
+            if used.checked_add(capacity).is_none_or(|total| total > limit) {
+                return Err(LuaError::runtime(format!(
+                    "Fatal: allocating {capacity} bytes would exceed the global memory limit \
+                     ({used} of {limit} bytes in use)"
+                )));
+            }
         }
+
+        let block = MemoryBlock::new(capacity, options, Rc::downgrade(self));
+        self.blocks.borrow_mut().push(block.clone());
+        Ok(block)
     }
 }
 
@@ -217,18 +910,21 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
 
     let malloc_registry = registry.clone();
     let clean_registry = registry.clone();
+    let collect_registry = registry.clone();
+    let limit_registry = registry.clone();
+    let used_registry = registry.clone();
 
     TableBuilder::new(lua.clone())?
-        .with_function("malloc", move |_, size: usize| {
-            if size == 0 {
-                return Err(LuaError::runtime("Cannot allocate zero-sized memory block"));
-            }
-
-            let block = MemoryBlock::new(size);
-            malloc_registry.blocks.borrow_mut().push(block.clone());
+        .with_function(
+            "malloc",
+            move |_, (size, options): (usize, BlockOptions)| {
+                if size == 0 {
+                    return Err(LuaError::runtime("Cannot allocate zero-sized memory block"));
+                }
 
-            Ok(block)
-        })?
+                malloc_registry.allocate(size, options)
+            },
+        )?
         .with_function("Clean", move |_, callback: LuaFunction| {
             let mut blocks = clean_registry.blocks.borrow_mut();
             let now = Instant::now();
@@ -245,9 +941,7 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
                 let should_clean: bool = callback.call(block.clone()).unwrap_or(false);
 
                 if expired || should_clean {
-                    inner.buffer.clear();
-                    inner.freed = true;
-                    inner.scheduled = None;
+                    MemoryBlock::clear(&mut inner);
                     return false;
                 }
 
@@ -256,5 +950,68 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
 
             Ok(())
         })?
+        .with_function("collect", move |_, ()| {
+            // Take the blocks out first, so the registry is not borrowed while freeing
+            let blocks = std::mem::take(&mut *collect_registry.blocks.borrow_mut());
+            let mut reclaimed = 0;
+
+            for block in blocks {
+                let mut inner = block.inner.borrow_mut();
+
+                if inner.freed {
+                    continue;
+                }
+
+                reclaimed += inner.used;
+                MemoryBlock::clear(&mut inner);
+            }
+
+            Ok(reclaimed)
+        })?
+        .with_function("setGlobalLimit", move |_, bytes: Option<usize>| {
+            limit_registry.limit.set(bytes);
+            Ok(())
+        })?
+        .with_function("globalUsed", move |_, ()| Ok(used_registry.used()))?
+        .with_function("weak", |_, block: LuaUserDataRef<MemoryBlock>| {
+            Ok(MemoryWeak {
+                block: Rc::downgrade(&block.inner),
+                registry: block.registry.clone(),
+            })
+        })?
         .build_readonly()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A write that measured the whole block again would also count the growth of earlier tables
+    #[test]
+    fn store_only_measures_the_new_value() {
+        let lua = Lua::new();
+        let block = MemoryBlock::new(1 << 20, BlockOptions::default(), Weak::new());
+        let mut inner = block.inner.borrow_mut();
+
+        let tables = (0..100)
+            .map(|_| lua.create_table())
+            .collect::<LuaResult<Vec<_>>>()
+            .unwrap();
+        for table in &tables {
+            MemoryBlock::store(&mut inner, LuaValue::Table(table.clone())).unwrap();
+        }
+        let used = inner.used;
+
+        for table in &tables {
+            table.raw_set("grown", "x".repeat(64)).unwrap();
+        }
+
+        for i in 0..1000 {
+            let before = inner.used;
+            MemoryBlock::store(&mut inner, LuaValue::Integer(i)).unwrap();
+            assert_eq!(inner.used - before, size_of::<i64>());
+        }
+        assert_eq!(inner.used, used + 1000 * size_of::<i64>());
+        assert_eq!(inner.sizes.iter().sum::<usize>(), inner.used);
+    }
+}
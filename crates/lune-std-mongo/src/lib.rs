@@ -3,19 +3,50 @@
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::too_many_lines)]
 
-use futures::StreamExt;
+use futures::{AsyncReadExt, AsyncWriteExt, TryStreamExt, lock::Mutex as AsyncMutex};
 use lune_utils::TableBuilder;
 use mlua::{UserData, UserDataMethods, prelude::*};
 use mongodb::{
-    Client,
-    bson::{Bson, DateTime, Document, oid::ObjectId},
+    Client, ClientSession, Cursor, SessionCursor,
+    bson::{Bson, DateTime, Document, Timestamp, doc, oid::ObjectId},
+    error::ErrorKind,
+    gridfs::GridFsBucket,
+    options::{
+        Collation, DeleteOneModel, GridFsBucketOptions, Hint, InsertOneModel, UpdateOneModel,
+        WriteModel,
+    },
+    results::{SummaryBulkWriteResult, UpdateResult},
+};
+use std::{
+    sync::{Arc, LazyLock},
+    time::Duration,
 };
-use std::sync::{Arc, LazyLock};
 use tokio::runtime::Runtime;
 
 static TOKIO_RUNTIME: LazyLock<Runtime> =
     LazyLock::new(|| Runtime::new().expect("Failed to create Tokio runtime"));
 
+// Server error code for operations exceeding their maxTimeMS
+const MAX_TIME_MS_EXPIRED: i32 = 50;
+
+// Server error code for commands the current user is not allowed to run
+const UNAUTHORIZED: i32 = 13;
+
+// Server error code for renaming onto a collection that already exists
+const NAMESPACE_EXISTS: i32 = 48;
+
+// Runs a query, as part of the given session if there is one
+macro_rules! run_in_session {
+    ($query:ident, $session:expr) => {
+        TOKIO_RUNTIME.block_on(async {
+            match &$session {
+                Some(session) => $query.session(&mut *session.inner.lock().await).await,
+                None => $query.await,
+            }
+        })
+    };
+}
+
 const TYPEDEFS: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/types.d.luau"));
 
 #[must_use]
@@ -54,6 +85,83 @@ impl UserData for LuaDateTime {
     }
 }
 
+#[derive(Clone)]
+pub struct LuaTimestamp {
+    inner: Timestamp,
+}
+
+impl UserData for LuaTimestamp {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("t", |_, this| Ok(this.inner.time));
+        fields.add_field_method_get("i", |_, this| Ok(this.inner.increment));
+    }
+}
+
+// The MinKey and MaxKey sentinels, which compare lower and higher than any other value
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LuaBsonKey {
+    Min,
+    Max,
+}
+
+impl UserData for LuaBsonKey {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // Keys read back from documents are new userdata, so they are compared by kind
+        methods.add_meta_method(LuaMetaMethod::Eq, |_, this, other: LuaAnyUserData| {
+            Ok(other
+                .borrow::<LuaBsonKey>()
+                .is_ok_and(|other| *this == *other))
+        });
+    }
+}
+
+// A number that is always stored as a specific BSON type, instead of one guessed from its Lua value
+#[derive(Clone)]
+pub struct LuaBsonNumber {
+    inner: Bson,
+}
+
+impl LuaBsonNumber {
+    fn type_name(&self) -> &'static str {
+        match self.inner {
+            Bson::Int32(_) => "int32",
+            Bson::Int64(_) => "int64",
+            _ => "double",
+        }
+    }
+}
+
+impl UserData for LuaBsonNumber {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("type", |_, this| Ok(this.type_name()));
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // 64-bit integers may not fit in a Lua number, so their exact value is only given as a string
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(match this.inner {
+                Bson::Int32(i) => i.to_string(),
+                Bson::Int64(i) => i.to_string(),
+                Bson::Double(n) => n.to_string(),
+                _ => unreachable!("bson numbers are only created as int32, int64 or double"),
+            })
+        });
+    }
+}
+
+/**
+    Converts a Lua number to an integer, erroring if it has a fractional part or
+    does not fit in the range of the BSON type, instead of silently truncating it.
+*/
+fn number_to_integer(n: f64, min: f64, max: f64, kind: &str) -> LuaResult<i64> {
+    if n.fract() != 0.0 || !(min..max).contains(&n) {
+        return Err(LuaError::runtime(format!(
+            "{kind} expected an integer in range, got {n}"
+        )));
+    }
+    Ok(n as i64)
+}
+
 #[derive(Clone)]
 pub struct LuaMongoClient {
     inner: Arc<Client>,
@@ -69,6 +177,59 @@ pub struct LuaMongoCollection {
     inner: mongodb::Collection<Document>,
 }
 
+#[derive(Clone)]
+pub struct LuaMongoSession {
+    inner: Arc<AsyncMutex<ClientSession>>,
+}
+
+#[derive(Clone)]
+pub struct LuaMongoGridFsBucket {
+    inner: GridFsBucket,
+}
+
+enum CursorState {
+    Plain(Cursor<Document>),
+    // Cursors opened in a session need the session to fetch more documents
+    Session(SessionCursor<Document>, LuaMongoSession),
+    Exhausted,
+}
+
+impl CursorState {
+    async fn next_document(&mut self) -> mongodb::error::Result<Option<Document>> {
+        let next = match self {
+            Self::Plain(cursor) => cursor.try_next().await?,
+            Self::Session(cursor, session) => {
+                let mut session = session.inner.lock().await;
+                cursor.next(&mut session).await.transpose()?
+            }
+            Self::Exhausted => None,
+        };
+
+        // Dropping the cursor as soon as it runs out frees it on the server
+        if next.is_none() {
+            *self = Self::Exhausted;
+        }
+
+        Ok(next)
+    }
+}
+
+/**
+    A cursor that fetches documents from the server in batches, as they are read.
+*/
+#[derive(Clone)]
+pub struct LuaMongoCursor {
+    inner: Arc<AsyncMutex<CursorState>>,
+}
+
+impl LuaMongoCursor {
+    fn new(state: CursorState) -> Self {
+        Self {
+            inner: Arc::new(AsyncMutex::new(state)),
+        }
+    }
+}
+
 async fn mongo_connect(_: Lua, uri: String) -> LuaResult<LuaMongoClient> {
     let client = TOKIO_RUNTIME
         .block_on(async {
@@ -89,6 +250,108 @@ impl UserData for LuaMongoClient {
                 inner: this.inner.database(&name),
             })
         });
+
+        methods.add_async_method("ping", |_, this, ()| async move {
+            ping(&this.inner).map_err(mongo_error_to_lua)?;
+            Ok(true)
+        });
+
+        methods.add_async_method("isConnected", |_, this, ()| async move {
+            Ok(ping(&this.inner).is_ok())
+        });
+
+        methods.add_async_method("listDatabaseNames", |lua, this, ()| async move {
+            let names = TOKIO_RUNTIME
+                .block_on(async { this.inner.list_database_names().await })
+                .map_err(mongo_error_to_lua)?;
+
+            lua.create_sequence_from(names)
+        });
+
+        methods.add_async_method("startSession", |_, this, ()| async move {
+            let session = TOKIO_RUNTIME
+                .block_on(async { this.inner.start_session().await })
+                .map_err(mongo_error_to_lua)?;
+
+            Ok(LuaMongoSession {
+                inner: Arc::new(AsyncMutex::new(session)),
+            })
+        });
+    }
+}
+
+impl UserData for LuaMongoSession {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method(
+            "withTransaction",
+            |_, this, callback: LuaFunction| async move {
+                TOKIO_RUNTIME
+                    .block_on(async { this.inner.lock().await.start_transaction().await })
+                    .map_err(mongo_error_to_lua)?;
+
+                // The session is not locked while the callback runs, so that
+                // collection methods called with it can lock it themselves
+                match callback.call_async::<LuaMultiValue>(this.clone()).await {
+                    Ok(values) => {
+                        TOKIO_RUNTIME
+                            .block_on(async { this.inner.lock().await.commit_transaction().await })
+                            .map_err(mongo_error_to_lua)?;
+                        Ok(values)
+                    }
+                    Err(err) => {
+                        // The callback error is more useful than any error from aborting
+                        let _ = TOKIO_RUNTIME
+                            .block_on(async { this.inner.lock().await.abort_transaction().await });
+                        Err(err)
+                    }
+                }
+            },
+        );
+    }
+}
+
+impl UserData for LuaMongoCursor {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method("next", |lua, this, ()| async move {
+            let doc = TOKIO_RUNTIME
+                .block_on(async { this.inner.lock().await.next_document().await })
+                .map_err(mongo_error_to_lua)?;
+
+            match doc {
+                Some(doc) => document_to_lua(lua, doc),
+                None => Ok(LuaValue::Nil),
+            }
+        });
+
+        methods.add_async_method("toArray", |lua, this, ()| async move {
+            let docs = TOKIO_RUNTIME
+                .block_on(async {
+                    let mut state = this.inner.lock().await;
+                    let mut docs = Vec::new();
+                    while let Some(doc) = state.next_document().await? {
+                        docs.push(doc);
+                    }
+                    Ok(docs)
+                })
+                .map_err(mongo_error_to_lua)?;
+
+            let result_table = lua.create_table_with_capacity(docs.len(), 0)?;
+            for doc in docs {
+                result_table.raw_push(document_to_lua(lua.clone(), doc)?)?;
+            }
+
+            Ok(result_table)
+        });
+
+        methods.add_async_method("run", |_, this, ()| async move {
+            TOKIO_RUNTIME
+                .block_on(async {
+                    let mut state = this.inner.lock().await;
+                    while state.next_document().await?.is_some() {}
+                    Ok(())
+                })
+                .map_err(mongo_error_to_lua)
+        });
     }
 }
 
@@ -99,35 +362,108 @@ impl UserData for LuaMongoDatabase {
                 inner: this.inner.collection::<Document>(&name),
             })
         });
+
+        methods.add_method("gridfs", |_, this, bucket_name: Option<String>| {
+            let options = GridFsBucketOptions::builder()
+                .bucket_name(bucket_name)
+                .build();
+
+            Ok(LuaMongoGridFsBucket {
+                inner: this.inner.gridfs_bucket(options),
+            })
+        });
+
+        methods.add_async_method("hasCollection", |_, this, name: String| async move {
+            let names = TOKIO_RUNTIME
+                .block_on(async {
+                    this.inner
+                        .list_collection_names()
+                        .filter(doc! { "name": &name })
+                        .await
+                })
+                .map_err(mongo_error_to_lua)?;
+
+            Ok(names.contains(&name))
+        });
+
+        methods.add_async_method("drop", |_, this, ()| async move {
+            TOKIO_RUNTIME
+                .block_on(async { this.inner.drop().await })
+                .map_err(|err| match err.kind.as_ref() {
+                    ErrorKind::Command(command) if command.code == UNAUTHORIZED => {
+                        LuaError::runtime(format!(
+                            "Not authorized to drop database '{}'",
+                            this.inner.name()
+                        ))
+                    }
+                    _ => mongo_error_to_lua(err),
+                })
+        });
+
+        methods.add_async_method(
+            "renameCollection",
+            |_, this, (from, to, options): (String, String, Option<LuaTable>)| async move {
+                let drop_target = match &options {
+                    Some(options) => options.get::<Option<bool>>("dropTarget")?,
+                    None => None,
+                };
+
+                let db_name = this.inner.name();
+                let command = doc! {
+                    "renameCollection": format!("{db_name}.{from}"),
+                    "to": format!("{db_name}.{to}"),
+                    "dropTarget": drop_target.unwrap_or(false),
+                };
+
+                // Renames can only be run against the admin database
+                let admin = this.inner.client().database("admin");
+                TOKIO_RUNTIME
+                    .block_on(async { admin.run_command(command).await })
+                    .map_err(|err| match err.kind.as_ref() {
+                        ErrorKind::Command(command) if command.code == NAMESPACE_EXISTS => {
+                            LuaError::runtime(format!(
+                                "Cannot rename collection '{from}' to '{to}', target exists"
+                            ))
+                        }
+                        _ => mongo_error_to_lua(err),
+                    })?;
+
+                Ok(())
+            },
+        );
     }
 }
 
 impl UserData for LuaMongoCollection {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_async_method("insertOne", |lua, this, value: LuaValue| async move {
-            let doc = lua_value_to_document(value.clone())?;
+        methods.add_async_method(
+            "insertOne",
+            |lua, this, (value, options): (LuaValue, Option<LuaTable>)| async move {
+                let doc = lua_value_to_strict_document(value.clone(), "insertOne")?;
+                let session = session_from_options(options.as_ref())?;
 
-            let result = TOKIO_RUNTIME
-                .block_on(async { this.inner.insert_one(doc).await })
-                .into_lua_err()?;
+                let query = this.inner.insert_one(doc);
+                let result = run_in_session!(query, session).into_lua_err()?;
 
-            if let Some(id) = result.inserted_id.as_object_id() {
-                let oid = LuaObjectId { inner: id };
+                if let Some(id) = result.inserted_id.as_object_id() {
+                    let oid = LuaObjectId { inner: id };
 
-                if let LuaValue::Table(table) = value {
-                    table.set("_id", lua.create_userdata(oid.clone())?)?;
-                }
+                    if let LuaValue::Table(table) = value {
+                        table.set("_id", lua.create_userdata(oid.clone())?)?;
+                    }
 
-                return Ok(LuaValue::UserData(lua.create_userdata(oid)?));
-            }
+                    return Ok(LuaValue::UserData(lua.create_userdata(oid)?));
+                }
 
-            Ok(LuaValue::Nil)
-        });
+                Ok(LuaValue::Nil)
+            },
+        );
 
         methods.add_async_method(
             "findOne",
             |lua, this, (filter_value, options): (LuaValue, Option<LuaTable>)| async move {
                 let filter = lua_value_to_document(filter_value)?;
+                let session = session_from_options(options.as_ref())?;
                 let mut query = this.inner.find_one(filter);
 
                 if let Some(opt_table) = options {
@@ -135,15 +471,21 @@ impl UserData for LuaMongoCollection {
                         let sort_doc = lua_value_to_document(sort)?;
                         query = query.sort(sort_doc);
                     }
-                    if let Ok(projection) = opt_table.get::<LuaValue>("projection") {
-                        let proj_doc = lua_value_to_document(projection)?;
+                    if let Some(proj_doc) = lua_value_to_projection(opt_table.get("projection")?)? {
                         query = query.projection(proj_doc);
                     }
+                    if let Some(hint) = opt_table.get::<Option<LuaValue>>("hint")? {
+                        query = query.hint(lua_value_to_hint(hint)?);
+                    }
+                    if let Some(collation) = opt_table.get::<Option<LuaValue>>("collation")? {
+                        query = query.collation(lua_value_to_collation(collation)?);
+                    }
+                    if let Ok(max_time_ms) = opt_table.get::<u64>("maxTimeMS") {
+                        query = query.max_time(Duration::from_millis(max_time_ms));
+                    }
                 }
 
-                let result = TOKIO_RUNTIME
-                    .block_on(async { query.await })
-                    .into_lua_err()?;
+                let result = run_in_session!(query, session).map_err(mongo_error_to_lua)?;
 
                 match result {
                     Some(doc) => document_to_lua(lua, doc),
@@ -152,10 +494,63 @@ impl UserData for LuaMongoCollection {
             },
         );
 
+        methods.add_async_method(
+            "findExactlyOne",
+            |lua, this, (filter_value, options): (LuaValue, Option<LuaTable>)| async move {
+                let filter = lua_value_to_document(filter_value)?;
+                let session = session_from_options(options.as_ref())?;
+                // A second match is all it takes to know the filter is not unique
+                let mut query = this.inner.find(filter).limit(2);
+
+                if let Some(opt_table) = options {
+                    if let Some(proj_doc) = lua_value_to_projection(opt_table.get("projection")?)? {
+                        query = query.projection(proj_doc);
+                    }
+                    if let Some(hint) = opt_table.get::<Option<LuaValue>>("hint")? {
+                        query = query.hint(lua_value_to_hint(hint)?);
+                    }
+                    if let Some(collation) = opt_table.get::<Option<LuaValue>>("collation")? {
+                        query = query.collation(lua_value_to_collation(collation)?);
+                    }
+                    if let Ok(max_time_ms) = opt_table.get::<u64>("maxTimeMS") {
+                        query = query.max_time(Duration::from_millis(max_time_ms));
+                    }
+                }
+
+                let mut docs: Vec<Document> = TOKIO_RUNTIME
+                    .block_on(async {
+                        match &session {
+                            Some(session) => {
+                                let mut session = session.inner.lock().await;
+                                let mut cursor = query.session(&mut *session).await?;
+                                let mut docs = Vec::new();
+                                while let Some(doc) = cursor.next(&mut session).await {
+                                    docs.push(doc?);
+                                }
+                                Ok(docs)
+                            }
+                            None => query.await?.try_collect().await,
+                        }
+                    })
+                    .map_err(mongo_error_to_lua)?;
+
+                match docs.len() {
+                    0 => Err(LuaError::runtime(
+                        "findExactlyOne found no documents matching the filter",
+                    )),
+                    1 => document_to_lua(lua, docs.remove(0)),
+                    _ => Err(LuaError::runtime(
+                        "findExactlyOne found multiple documents matching the filter",
+                    )),
+                }
+            },
+        );
+
         methods.add_async_method(
             "find",
             |lua, this, (filter_value, options): (LuaValue, Option<LuaTable>)| async move {
                 let filter = lua_value_to_document(filter_value)?;
+                let session = session_from_options(options.as_ref())?;
                 let mut query = this.inner.find(filter);
 
                 if let Some(opt_table) = options {
@@ -169,23 +564,41 @@ impl UserData for LuaMongoCollection {
                     if let Ok(skip) = opt_table.get::<u64>("skip") {
                         query = query.skip(skip);
                     }
-                    if let Ok(projection) = opt_table.get::<LuaValue>("projection") {
-                        let proj_doc = lua_value_to_document(projection)?;
+                    if let Some(proj_doc) = lua_value_to_projection(opt_table.get("projection")?)? {
                         query = query.projection(proj_doc);
                     }
+                    if let Some(hint) = opt_table.get::<Option<LuaValue>>("hint")? {
+                        query = query.hint(lua_value_to_hint(hint)?);
+                    }
+                    if let Some(collation) = opt_table.get::<Option<LuaValue>>("collation")? {
+                        query = query.collation(lua_value_to_collation(collation)?);
+                    }
+                    if let Ok(max_time_ms) = opt_table.get::<u64>("maxTimeMS") {
+                        query = query.max_time(Duration::from_millis(max_time_ms));
+                    }
                 }
 
-                let mut cursor = TOKIO_RUNTIME
-                    .block_on(async { query.await })
-                    .into_lua_err()?;
-
-                let result_table = lua.create_table()?;
-                let mut index = 1;
+                // Cursors for queries in a session need the session to fetch more documents
+                let docs: Vec<Document> = TOKIO_RUNTIME
+                    .block_on(async {
+                        match &session {
+                            Some(session) => {
+                                let mut session = session.inner.lock().await;
+                                let mut cursor = query.session(&mut *session).await?;
+                                let mut docs = Vec::new();
+                                while let Some(doc) = cursor.next(&mut session).await {
+                                    docs.push(doc?);
+                                }
+                                Ok(docs)
+                            }
+                            None => query.await?.try_collect().await,
+                        }
+                    })
+                    .map_err(mongo_error_to_lua)?;
 
-                while let Some(doc) = TOKIO_RUNTIME.block_on(async { cursor.next().await }) {
-                    let doc = doc.into_lua_err()?;
-                    result_table.set(index, document_to_lua(lua.clone(), doc)?)?;
-                    index += 1;
+                let result_table = lua.create_table_with_capacity(docs.len(), 0)?;
+                for doc in docs {
+                    result_table.raw_push(document_to_lua(lua.clone(), doc)?)?;
                 }
 
                 Ok(result_table)
@@ -194,9 +607,10 @@ impl UserData for LuaMongoCollection {
 
         methods.add_async_method(
             "updateOne",
-            |_, this, (f, u, options): (LuaValue, LuaValue, Option<LuaTable>)| async move {
+            |lua, this, (f, u, options): (LuaValue, LuaValue, Option<LuaTable>)| async move {
                 let filter = lua_value_to_document(f)?;
                 let update = lua_value_to_document(u)?;
+                let session = session_from_options(options.as_ref())?;
                 let mut query = this.inner.update_one(filter, update);
 
                 if let Some(opt_table) = options {
@@ -205,19 +619,18 @@ impl UserData for LuaMongoCollection {
                     }
                 }
 
-                TOKIO_RUNTIME
-                    .block_on(async { query.await })
-                    .into_lua_err()?;
+                let result = run_in_session!(query, session).into_lua_err()?;
 
-                Ok(())
+                update_result_to_lua(lua, result)
             },
         );
 
         methods.add_async_method(
             "updateMany",
-            |_, this, (f, u, options): (LuaValue, LuaValue, Option<LuaTable>)| async move {
+            |lua, this, (f, u, options): (LuaValue, LuaValue, Option<LuaTable>)| async move {
                 let filter = lua_value_to_document(f)?;
                 let update = lua_value_to_document(u)?;
+                let session = session_from_options(options.as_ref())?;
                 let mut query = this.inner.update_many(filter, update);
 
                 if let Some(opt_table) = options {
@@ -226,39 +639,370 @@ impl UserData for LuaMongoCollection {
                     }
                 }
 
-                TOKIO_RUNTIME
-                    .block_on(async { query.await })
-                    .into_lua_err()?;
+                let result = run_in_session!(query, session).into_lua_err()?;
+
+                update_result_to_lua(lua, result)
+            },
+        );
+
+        methods.add_async_method(
+            "replaceOne",
+            |lua, this, (f, r, options): (LuaValue, LuaValue, Option<LuaTable>)| async move {
+                let filter = lua_value_to_document(f)?;
+                let replacement = lua_value_to_strict_document(r, "replaceOne")?;
+                let session = session_from_options(options.as_ref())?;
+                let mut query = this.inner.replace_one(filter, replacement);
 
+                if let Some(opt_table) = options {
+                    if let Ok(upsert) = opt_table.get::<bool>("upsert") {
+                        query = query.upsert(upsert);
+                    }
+                }
+
+                let result = run_in_session!(query, session).into_lua_err()?;
+
+                update_result_to_lua(lua, result)
+            },
+        );
+
+        methods.add_async_method(
+            "deleteOne",
+            |_, this, (filter, options): (LuaValue, Option<LuaTable>)| async move {
+                let filter = lua_value_to_document(filter)?;
+                let session = session_from_options(options.as_ref())?;
+                let query = this.inner.delete_one(filter);
+                run_in_session!(query, session).into_lua_err()?;
                 Ok(())
             },
         );
 
-        methods.add_async_method("deleteOne", |_, this, filter| async move {
-            let filter = lua_value_to_document(filter)?;
-            TOKIO_RUNTIME
-                .block_on(async { this.inner.delete_one(filter).await })
-                .into_lua_err()?;
-            Ok(())
-        });
+        methods.add_async_method(
+            "deleteMany",
+            |_, this, (filter, options): (LuaValue, Option<LuaTable>)| async move {
+                let filter = lua_value_to_document(filter)?;
+                let session = session_from_options(options.as_ref())?;
+                let query = this.inner.delete_many(filter);
+                run_in_session!(query, session).into_lua_err()?;
+                Ok(())
+            },
+        );
 
-        methods.add_async_method("deleteMany", |_, this, filter| async move {
-            let filter = lua_value_to_document(filter)?;
-            TOKIO_RUNTIME
-                .block_on(async { this.inner.delete_many(filter).await })
+        methods.add_async_method(
+            "bulkWrite",
+            |lua, this, (operations, options): (LuaTable, Option<LuaTable>)| async move {
+                let namespace = this.inner.namespace();
+                let mut models = Vec::<WriteModel>::new();
+
+                for operation in operations.sequence_values::<LuaTable>() {
+                    let operation = operation?;
+
+                    // Operations are told apart by which field is set, so that a field
+                    // with the wrong type errors instead of being treated as missing
+                    let insert = operation.get::<LuaValue>("insertOne")?;
+                    let update = operation.get::<LuaValue>("updateOne")?;
+                    let delete = operation.get::<LuaValue>("deleteOne")?;
+
+                    if !insert.is_nil() {
+                        let document = lua_value_to_strict_document(insert, "bulkWrite insertOne")?;
+                        models.push(
+                            InsertOneModel::builder()
+                                .namespace(namespace.clone())
+                                .document(document)
+                                .build()
+                                .into(),
+                        );
+                    } else if !update.is_nil() {
+                        let op = bulk_write_operation_table(update, "updateOne")?;
+                        let filter = lua_value_to_document(op.get("filter")?)?;
+                        let update = lua_value_to_document(op.get("update")?)?;
+                        let upsert = op.get::<Option<bool>>("upsert")?;
+                        models.push(
+                            UpdateOneModel::builder()
+                                .namespace(namespace.clone())
+                                .filter(filter)
+                                .update(update)
+                                .upsert(upsert)
+                                .build()
+                                .into(),
+                        );
+                    } else if !delete.is_nil() {
+                        let op = bulk_write_operation_table(delete, "deleteOne")?;
+                        let filter = lua_value_to_document(op.get("filter")?)?;
+                        models.push(
+                            DeleteOneModel::builder()
+                                .namespace(namespace.clone())
+                                .filter(filter)
+                                .build()
+                                .into(),
+                        );
+                    } else {
+                        return Err(LuaError::runtime(
+                            "bulkWrite operations must be insertOne, updateOne or deleteOne",
+                        ));
+                    }
+                }
+
+                let session = session_from_options(options.as_ref())?;
+                let mut query = this.inner.client().bulk_write(models);
+
+                if let Some(opt_table) = options {
+                    if let Ok(ordered) = opt_table.get::<bool>("ordered") {
+                        query = query.ordered(ordered);
+                    }
+                }
+
+                let result = run_in_session!(query, session).into_lua_err()?;
+
+                bulk_write_result_to_lua(lua, result)
+            },
+        );
+
+        methods.add_async_method(
+            "aggregate",
+            |_, this, (pipeline, options): (LuaTable, Option<LuaTable>)| async move {
+                let mut stages = Vec::new();
+                for stage in pipeline.sequence_values::<LuaValue>() {
+                    stages.push(lua_value_to_strict_document(stage?, "aggregate")?);
+                }
+
+                let session = session_from_options(options.as_ref())?;
+                let mut query = this.inner.aggregate(stages);
+
+                if let Some(opt_table) = options {
+                    if let Ok(allow_disk_use) = opt_table.get::<bool>("allowDiskUse") {
+                        query = query.allow_disk_use(allow_disk_use);
+                    }
+                    if let Ok(batch_size) = opt_table.get::<u32>("batchSize") {
+                        query = query.batch_size(batch_size);
+                    }
+                    if let Ok(max_time_ms) = opt_table.get::<u64>("maxTimeMS") {
+                        query = query.max_time(Duration::from_millis(max_time_ms));
+                    }
+                }
+
+                // Write stages such as $out and $merge run as part of this first request
+                let state = TOKIO_RUNTIME
+                    .block_on(async {
+                        match session {
+                            Some(session) => {
+                                let cursor =
+                                    query.session(&mut *session.inner.lock().await).await?;
+                                Ok(CursorState::Session(cursor, session))
+                            }
+                            None => Ok(CursorState::Plain(query.await?)),
+                        }
+                    })
+                    .map_err(mongo_error_to_lua)?;
+
+                Ok(LuaMongoCursor::new(state))
+            },
+        );
+
+        methods.add_async_method(
+            "countDocuments",
+            |_, this, (filter_value, options): (LuaValue, Option<LuaTable>)| async move {
+                let filter = lua_value_to_document(filter_value)?;
+                let session = session_from_options(options.as_ref())?;
+                let mut query = this.inner.count_documents(filter);
+
+                if let Some(opt_table) = options {
+                    if let Ok(limit) = opt_table.get::<u64>("limit") {
+                        query = query.limit(limit);
+                    }
+                    if let Ok(skip) = opt_table.get::<u64>("skip") {
+                        query = query.skip(skip);
+                    }
+                    if let Ok(max_time_ms) = opt_table.get::<u64>("maxTimeMS") {
+                        query = query.max_time(Duration::from_millis(max_time_ms));
+                    }
+                }
+
+                let count = run_in_session!(query, session).map_err(mongo_error_to_lua)?;
+                // Counts are returned as integers, instead of silently losing precision as a float
+                i64::try_from(count).map_err(|_| {
+                    LuaError::runtime(format!("countDocuments count {count} is too large"))
+                })
+            },
+        );
+
+        methods.add_async_method(
+            "exists",
+            |_, this, (filter_value, options): (LuaValue, Option<LuaTable>)| async move {
+                let filter = lua_value_to_document(filter_value)?;
+                let session = session_from_options(options.as_ref())?;
+                // Only whether anything matched is needed, so nothing but the id is fetched
+                let mut query = this.inner.find_one(filter).projection(doc! { "_id": 1 });
+
+                if let Some(opt_table) = options {
+                    if let Some(hint) = opt_table.get::<Option<LuaValue>>("hint")? {
+                        query = query.hint(lua_value_to_hint(hint)?);
+                    }
+                    if let Some(collation) = opt_table.get::<Option<LuaValue>>("collation")? {
+                        query = query.collation(lua_value_to_collation(collation)?);
+                    }
+                    if let Ok(max_time_ms) = opt_table.get::<u64>("maxTimeMS") {
+                        query = query.max_time(Duration::from_millis(max_time_ms));
+                    }
+                }
+
+                let found = run_in_session!(query, session).map_err(mongo_error_to_lua)?;
+                Ok(found.is_some())
+            },
+        );
+    }
+}
+
+impl UserData for LuaMongoGridFsBucket {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method(
+            "upload",
+            |lua, this, (filename, data): (String, LuaString)| async move {
+                let bytes = data.as_bytes();
+
+                // The upload stream splits the data into chunks as it is written
+                let id = TOKIO_RUNTIME
+                    .block_on(async {
+                        let mut stream = this.inner.open_upload_stream(filename).await?;
+                        stream.write_all(&bytes).await?;
+                        stream.close().await?;
+                        Ok::<_, mongodb::error::Error>(stream.id().clone())
+                    })
+                    .into_lua_err()?;
+
+                bson_to_lua(lua, id)
+            },
+        );
+
+        methods.add_async_method("download", |lua, this, id: LuaValue| async move {
+            let id = lua_to_bson(id)?;
+
+            let bytes = TOKIO_RUNTIME
+                .block_on(async {
+                    let mut stream = this.inner.open_download_stream(id).await?;
+                    let mut bytes = Vec::new();
+                    stream.read_to_end(&mut bytes).await?;
+                    Ok::<_, mongodb::error::Error>(bytes)
+                })
                 .into_lua_err()?;
-            Ok(())
-        });
 
-        methods.add_async_method("countDocuments", |_, this, filter| async move {
-            let filter = lua_value_to_document(filter)?;
-            TOKIO_RUNTIME
-                .block_on(async { this.inner.count_documents(filter).await })
-                .into_lua_err()
+            lua.create_string(bytes)
         });
     }
 }
 
+fn ping(client: &Client) -> mongodb::error::Result<()> {
+    TOKIO_RUNTIME.block_on(async {
+        client
+            .database("admin")
+            .run_command(doc! { "ping": 1 })
+            .await
+            .map(|_| ())
+    })
+}
+
+fn session_from_options(options: Option<&LuaTable>) -> LuaResult<Option<LuaMongoSession>> {
+    match options {
+        Some(opt_table) => Ok(opt_table
+            .get::<Option<LuaUserDataRef<LuaMongoSession>>>("session")?
+            .map(|session| session.clone())),
+        None => Ok(None),
+    }
+}
+
+fn mongo_error_to_lua(err: mongodb::error::Error) -> LuaError {
+    match err.kind.as_ref() {
+        ErrorKind::Command(command) if command.code == MAX_TIME_MS_EXPIRED => {
+            LuaError::runtime("Operation exceeded the time limit set by maxTimeMS")
+        }
+        _ => err.into_lua_err(),
+    }
+}
+
+fn update_result_to_lua(lua: Lua, result: UpdateResult) -> LuaResult<LuaTable> {
+    let upserted_id = match result.upserted_id {
+        Some(id) => bson_to_lua(lua.clone(), id)?,
+        None => LuaValue::Nil,
+    };
+
+    TableBuilder::new(lua)?
+        .with_value("matchedCount", result.matched_count)?
+        .with_value("modifiedCount", result.modified_count)?
+        .with_value("upsertedId", upserted_id)?
+        .build_readonly()
+}
+
+fn bulk_write_result_to_lua(lua: Lua, result: SummaryBulkWriteResult) -> LuaResult<LuaTable> {
+    TableBuilder::new(lua)?
+        .with_value("insertedCount", result.inserted_count)?
+        .with_value("matchedCount", result.matched_count)?
+        .with_value("modifiedCount", result.modified_count)?
+        .with_value("upsertedCount", result.upserted_count)?
+        .with_value("deletedCount", result.deleted_count)?
+        .build_readonly()
+}
+
+fn lua_value_to_hint(value: LuaValue) -> LuaResult<Hint> {
+    match value {
+        LuaValue::String(name) => Ok(Hint::Name(name.to_str()?.to_string())),
+        keys => Ok(Hint::Keys(lua_value_to_document(keys)?)),
+    }
+}
+
+// Projections can also be given as a list of field names, the same as including each of them
+fn lua_value_to_projection(value: LuaValue) -> LuaResult<Option<Document>> {
+    let table = match value {
+        LuaValue::Nil => return Ok(None),
+        LuaValue::Table(table) => table,
+        value => {
+            return Err(LuaError::runtime(format!(
+                "projection expected a table, got {}",
+                value.type_name()
+            )));
+        }
+    };
+
+    if table.raw_len() == 0 {
+        return lua_value_to_document(LuaValue::Table(table)).map(Some);
+    }
+
+    let mut doc = Document::new();
+    for pair in table.pairs::<LuaValue, LuaValue>() {
+        match pair? {
+            (LuaValue::Integer(_), LuaValue::String(field)) => {
+                doc.insert(field.to_str()?.to_string(), Bson::Int32(1));
+            }
+            (LuaValue::Integer(_), value) => {
+                return Err(LuaError::runtime(format!(
+                    "projection field names must be strings, got {}",
+                    value.type_name()
+                )));
+            }
+            _ => {
+                return Err(LuaError::runtime(
+                    "projection must be either a document or a list of field names, not both",
+                ));
+            }
+        }
+    }
+
+    Ok(Some(doc))
+}
+
+fn lua_value_to_collation(value: LuaValue) -> LuaResult<Collation> {
+    let mut doc = lua_value_to_document(value)?;
+
+    // Lua numbers arrive as doubles, but levels such as strength must be integers
+    for (_, field) in doc.iter_mut() {
+        if let Bson::Double(n) = field
+            && n.fract() == 0.0
+        {
+            *field = Bson::Int32(*n as i32);
+        }
+    }
+
+    mongodb::bson::from_document(doc).into_lua_err()
+}
+
 fn lua_value_to_document(value: LuaValue) -> LuaResult<Document> {
     match lua_to_bson(value)? {
         Bson::Document(doc) => Ok(doc),
@@ -266,6 +1010,28 @@ fn lua_value_to_document(value: LuaValue) -> LuaResult<Document> {
     }
 }
 
+fn bulk_write_operation_table(value: LuaValue, kind: &str) -> LuaResult<LuaTable> {
+    match value {
+        LuaValue::Table(table) => Ok(table),
+        _ => Err(LuaError::runtime(format!(
+            "bulkWrite {kind} expected a table with its filter, got {}",
+            value.type_name()
+        ))),
+    }
+}
+
+// Unlike filters, where an empty document is legitimate, documents being
+// written must come from a table instead of silently becoming empty
+fn lua_value_to_strict_document(value: LuaValue, method: &str) -> LuaResult<Document> {
+    match value {
+        LuaValue::Table(_) => lua_value_to_document(value),
+        _ => Err(LuaError::runtime(format!(
+            "{method} expected a table to use as the document, got {}",
+            value.type_name()
+        ))),
+    }
+}
+
 fn lua_to_bson(value: LuaValue) -> LuaResult<Bson> {
     Ok(match value {
         LuaValue::Boolean(b) => Bson::Boolean(b),
@@ -277,6 +1043,15 @@ fn lua_to_bson(value: LuaValue) -> LuaResult<Bson> {
                 Bson::ObjectId(oid.inner)
             } else if let Ok(dt) = ud.borrow::<LuaDateTime>() {
                 Bson::DateTime(dt.inner)
+            } else if let Ok(ts) = ud.borrow::<LuaTimestamp>() {
+                Bson::Timestamp(ts.inner)
+            } else if let Ok(key) = ud.borrow::<LuaBsonKey>() {
+                match *key {
+                    LuaBsonKey::Min => Bson::MinKey,
+                    LuaBsonKey::Max => Bson::MaxKey,
+                }
+            } else if let Ok(number) = ud.borrow::<LuaBsonNumber>() {
+                number.inner.clone()
             } else {
                 Bson::Null
             }
@@ -321,6 +1096,11 @@ fn bson_to_lua(lua: Lua, value: Bson) -> LuaResult<LuaValue> {
         Bson::String(s) => LuaValue::String(lua.create_string(&s)?),
         Bson::ObjectId(oid) => LuaValue::UserData(lua.create_userdata(LuaObjectId { inner: oid })?),
         Bson::DateTime(dt) => LuaValue::UserData(lua.create_userdata(LuaDateTime { inner: dt })?),
+        Bson::Timestamp(ts) => LuaValue::UserData(lua.create_userdata(LuaTimestamp { inner: ts })?),
+        Bson::MinKey => LuaValue::UserData(lua.create_userdata(LuaBsonKey::Min)?),
+        Bson::MaxKey => LuaValue::UserData(lua.create_userdata(LuaBsonKey::Max)?),
+        // Code is only ever read back as its source, it is written as a plain string
+        Bson::JavaScriptCode(code) => LuaValue::String(lua.create_string(&code)?),
         Bson::Document(doc) => document_to_lua(lua, doc)?,
         _ => LuaValue::Nil,
     })
@@ -347,5 +1127,59 @@ fn create_object_api(lua: &Lua) -> LuaResult<LuaTable> {
         })?,
     )?;
 
+    table.set(
+        "timestamp",
+        lua.create_function(|lua, (time, increment): (u32, u32)| {
+            lua.create_userdata(LuaTimestamp {
+                inner: Timestamp { time, increment },
+            })
+        })?,
+    )?;
+
+    table.set(
+        "int32",
+        lua.create_function(|lua, n: f64| {
+            let i = number_to_integer(n, f64::from(i32::MIN), -f64::from(i32::MIN), "int32")?;
+            lua.create_userdata(LuaBsonNumber {
+                inner: Bson::Int32(i as i32),
+            })
+        })?,
+    )?;
+
+    table.set(
+        "int64",
+        lua.create_function(|lua, n: f64| {
+            // i64::MAX is not exactly representable, so the range is checked against 2^63 instead
+            let i = number_to_integer(n, i64::MIN as f64, -(i64::MIN as f64), "int64")?;
+            lua.create_userdata(LuaBsonNumber {
+                inner: Bson::Int64(i),
+            })
+        })?,
+    )?;
+
+    table.set(
+        "double",
+        lua.create_function(|lua, n: f64| {
+            lua.create_userdata(LuaBsonNumber {
+                inner: Bson::Double(n),
+            })
+        })?,
+    )?;
+
+    table.set(
+        "long",
+        lua.create_function(|lua, s: String| {
+            let i = s.trim().parse::<i64>().map_err(|_| {
+                LuaError::runtime(format!("long expected a 64-bit integer string, got '{s}'"))
+            })?;
+            lua.create_userdata(LuaBsonNumber {
+                inner: Bson::Int64(i),
+            })
+        })?,
+    )?;
+
+    table.set("minKey", lua.create_userdata(LuaBsonKey::Min)?)?;
+    table.set("maxKey", lua.create_userdata(LuaBsonKey::Max)?)?;
+
     Ok(table)
 }
@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::convert::Infallible;
 use std::pin::Pin;
 use std::task::{Context, Poll};
@@ -15,17 +16,40 @@ use super::cursor::ReadableBodyCursor;
 
     If the body was created from a `Vec<u8>`, `Bytes`, or a `String`, reading
     bytes is always safe and does not go through any additional indirections.
+
+    A body may also be made up of several chunks, in which case it has no known
+    size and is sent using chunked transfer encoding, one frame per chunk.
 */
 #[derive(Debug, Clone)]
 pub struct ReadableBody {
     cursor: Option<ReadableBodyCursor>,
+    chunks: Option<VecDeque<ReadableBodyCursor>>,
 }
 
 impl ReadableBody {
     pub const fn empty() -> Self {
-        Self { cursor: None }
+        Self {
+            cursor: None,
+            chunks: None,
+        }
     }
 
+    /**
+        Creates a new chunked body from the given bodies, in order.
+    */
+    pub fn chunked(bodies: impl IntoIterator<Item = Self>) -> Self {
+        let chunks = bodies.into_iter().filter_map(|body| body.cursor).collect();
+        Self {
+            cursor: None,
+            chunks: Some(chunks),
+        }
+    }
+
+    /**
+        Returns the bytes of the body.
+
+        Chunked bodies are never contiguous, and will always return an empty slice.
+    */
     pub fn as_slice(&self) -> &[u8] {
         match self.cursor.as_ref() {
             Some(cursor) => cursor.as_slice(),
@@ -34,6 +58,13 @@ impl ReadableBody {
     }
 
     pub fn into_bytes(self) -> Bytes {
+        if let Some(chunks) = self.chunks {
+            let mut bytes = Vec::new();
+            for chunk in chunks {
+                bytes.extend_from_slice(chunk.as_slice());
+            }
+            return Bytes::from(bytes);
+        }
         match self.cursor {
             Some(cursor) => cursor.into_bytes(),
             None => Bytes::new(),
@@ -49,14 +80,25 @@ impl Body for ReadableBody {
         mut self: Pin<&mut Self>,
         _cx: &mut Context<'_>,
     ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
-        Poll::Ready(self.cursor.take().map(|d| Ok(Frame::data(d))))
+        let next = match self.chunks.as_mut() {
+            Some(chunks) => chunks.pop_front(),
+            None => self.cursor.take(),
+        };
+        Poll::Ready(next.map(|d| Ok(Frame::data(d))))
     }
 
     fn is_end_stream(&self) -> bool {
-        self.cursor.is_none()
+        match self.chunks.as_ref() {
+            Some(chunks) => chunks.is_empty(),
+            None => self.cursor.is_none(),
+        }
     }
 
     fn size_hint(&self) -> SizeHint {
+        // An unknown size makes hyper use chunked transfer encoding
+        if self.chunks.is_some() {
+            return SizeHint::default();
+        }
         self.cursor.as_ref().map_or_else(
             || SizeHint::with_exact(0),
             |c| SizeHint::with_exact(c.len() as u64),
@@ -71,6 +113,7 @@ where
     fn from(value: T) -> Self {
         Self {
             cursor: Some(value.into()),
+            chunks: None,
         }
     }
 }
@@ -82,6 +125,7 @@ where
     fn from(value: Option<T>) -> Self {
         Self {
             cursor: value.map(Into::into),
+            chunks: None,
         }
     }
 }
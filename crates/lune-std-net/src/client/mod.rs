@@ -1,3 +1,4 @@
+use async_io::Timer;
 use hyper::{Method, Response as HyperResponse, Uri, body::Incoming, header::LOCATION};
 
 use mlua::prelude::*;
@@ -7,11 +8,12 @@ use crate::{
     body::ReadableBody,
     client::{
         stream::{MaybeTlsStream, WsStream},
-        tcp::TcpConfig,
+        tcp::{TcpConfig, TcpRetryConfig},
     },
     shared::{request::Request, tcp::Tcp, websocket::Websocket},
 };
 
+pub mod pool;
 pub mod rustls;
 pub mod stream;
 pub mod tcp;
@@ -49,6 +51,35 @@ pub async fn connect_tcp(host: String, port: u16, config: TcpConfig) -> LuaResul
     Ok(Tcp::from(stream))
 }
 
+/**
+    Connects using plain TCP using the given host, port, and config,
+    retrying with exponential backoff until the connection succeeds
+    or the configured number of attempts has been exhausted.
+*/
+pub async fn connect_tcp_retry(host: String, port: u16, config: TcpRetryConfig) -> LuaResult<Tcp> {
+    let mut delay = config.base_delay;
+    let mut attempt = 1;
+
+    loop {
+        match connect_tcp(host.clone(), port, config.tcp).await {
+            Ok(tcp) => return Ok(tcp),
+            Err(err) if attempt >= config.attempts => {
+                return Err(LuaError::runtime(format!(
+                    "Failed to connect to {host}:{port} after {attempt} attempts: {err}"
+                )));
+            }
+            Err(_) => {}
+        }
+
+        // Jitter the delay so that many clients reconnecting at once spread out
+        let jittered = delay.mul_f64(0.5 + fastrand::f64() * 0.5);
+        Timer::after(jittered).await;
+
+        delay = delay.saturating_mul(2).min(config.max_delay);
+        attempt += 1;
+    }
+}
+
 fn try_follow_redirect(
     url: &mut Url,
     request: &mut Request,
@@ -0,0 +1,128 @@
+use std::{
+    io::Error,
+    sync::{Arc, Mutex},
+};
+
+use mlua::prelude::*;
+
+use crate::{
+    client::{connect_tcp, tcp::TcpConfig},
+    shared::tcp::Tcp,
+};
+
+const DEFAULT_MAX_IDLE: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct TcpPoolConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub max_idle: usize,
+}
+
+impl FromLua for TcpPoolConfig {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let LuaValue::Table(tab) = value else {
+            return Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("TcpPoolConfig"),
+                message: Some(String::from("expected a table with a host and port")),
+            });
+        };
+
+        Ok(Self {
+            host: tab.get("host")?,
+            port: tab.get("port")?,
+            tls: tab.get::<Option<bool>>("tls")?.unwrap_or_default(),
+            max_idle: tab
+                .get::<Option<usize>>("maxIdle")?
+                .unwrap_or(DEFAULT_MAX_IDLE),
+        })
+    }
+}
+
+/**
+    A pool of outbound TCP connections to a single host and port.
+
+    Released connections are kept around while they are still connected,
+    up to `max_idle` of them, and handed out again by the next acquire.
+*/
+#[derive(Debug, Clone)]
+pub struct TcpPool {
+    config: Arc<TcpPoolConfig>,
+    idle: Arc<Mutex<Vec<Tcp>>>,
+}
+
+impl TcpPool {
+    pub fn new(config: TcpPoolConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            idle: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn pop_idle(&self) -> Option<Tcp> {
+        self.idle.lock().expect("pool lock poisoned").pop()
+    }
+
+    fn idle_count(&self) -> usize {
+        self.idle.lock().expect("pool lock poisoned").len()
+    }
+
+    async fn acquire(&self) -> LuaResult<Tcp> {
+        // The most recently released connection is the least likely to have gone stale
+        while let Some(tcp) = self.pop_idle() {
+            if tcp.is_reusable().await {
+                return Ok(tcp.leased(self.clone()));
+            }
+            let _ = tcp.shutdown().await;
+        }
+
+        let config = TcpConfig {
+            tls: Some(self.config.tls),
+            ttl: None,
+        };
+        let tcp = connect_tcp(self.config.host.clone(), self.config.port, config).await?;
+        Ok(tcp.leased(self.clone()))
+    }
+
+    /**
+        Takes back a connection that is no longer in use, closing it instead if
+        it is dead, still has data to read, or the pool already has enough.
+    */
+    pub(crate) async fn release(&self, tcp: Tcp) -> Result<(), Error> {
+        if tcp.is_reusable().await {
+            let mut idle = self.idle.lock().expect("pool lock poisoned");
+            if idle.len() < self.config.max_idle {
+                idle.push(tcp);
+                return Ok(());
+            }
+        }
+        tcp.shutdown().await
+    }
+
+    async fn close(&self) -> Result<(), Error> {
+        let idle = std::mem::take(&mut *self.idle.lock().expect("pool lock poisoned"));
+        for tcp in idle {
+            let _ = tcp.shutdown().await;
+        }
+        Ok(())
+    }
+}
+
+impl LuaUserData for TcpPool {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("idleCount", |_, this| Ok(this.idle_count()));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method("acquire", |_, this, (): ()| {
+            let this = this.clone();
+            async move { this.acquire().await }
+        });
+        methods.add_async_method("close", |_, this, (): ()| {
+            let this = this.clone();
+            async move { this.close().await.into_lua_err() }
+        });
+    }
+}
@@ -107,6 +107,36 @@ impl MaybeTlsStream {
     pub fn set_ttl(&self, ttl: u32) -> Result<()> {
         self.as_ref().set_ttl(ttl)
     }
+
+    /**
+        Returns the raw socket descriptor for the stream, or `None` if it is encrypted using TLS.
+
+        On Unix this is the file descriptor, and on Windows the `SOCKET` handle.
+        The descriptor is still owned by the stream and must not be closed.
+    */
+    pub fn raw_fd(&self) -> Option<i64> {
+        let MaybeTlsStream::Plain(stream) = self else {
+            return None;
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+            Some(i64::from(stream.as_raw_fd()))
+        }
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::io::AsRawSocket;
+            i64::try_from(stream.as_raw_socket()).ok()
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = stream;
+            None
+        }
+    }
 }
 
 impl AsRef<TcpStream> for MaybeTlsStream {
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use mlua::prelude::*;
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -35,3 +37,56 @@ impl FromLua for TcpConfig {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct TcpRetryConfig {
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub tcp: TcpConfig,
+}
+
+impl Default for TcpRetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            tcp: TcpConfig::default(),
+        }
+    }
+}
+
+impl FromLua for TcpRetryConfig {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        if let LuaValue::Nil = value {
+            Ok(TcpRetryConfig::default())
+        } else if let LuaValue::Table(tab) = value {
+            let mut this = TcpRetryConfig {
+                tcp: TcpConfig::from_lua(LuaValue::Table(tab.clone()), lua)?,
+                ..TcpRetryConfig::default()
+            };
+
+            if let Some(attempts) = tab.get::<Option<u32>>("attempts")? {
+                if attempts == 0 {
+                    return Err(LuaError::runtime("attempts must be at least 1"));
+                }
+                this.attempts = attempts;
+            }
+            if let Some(base_delay) = tab.get::<Option<u64>>("baseDelayMs")? {
+                this.base_delay = Duration::from_millis(base_delay);
+            }
+            if let Some(max_delay) = tab.get::<Option<u64>>("maxDelayMs")? {
+                this.max_delay = Duration::from_millis(max_delay);
+            }
+
+            Ok(this)
+        } else {
+            Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("TcpRetryConfig"),
+                message: None,
+            })
+        }
+    }
+}
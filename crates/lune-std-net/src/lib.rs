@@ -11,12 +11,16 @@ pub(crate) mod url;
 
 use crate::shared::{
     hyper::HyperExecutor,
-    tcp::{Tcp, TcpHost},
-    udp::Udp,
+    tcp::{Tcp, TcpHost, TcpHostConfig},
+    udp::{Udp, UdpBindConfig},
 };
 
 use self::{
-    client::{stream::WsStream, tcp::TcpConfig},
+    client::{
+        pool::{TcpPool, TcpPoolConfig},
+        stream::WsStream,
+        tcp::{TcpConfig, TcpRetryConfig},
+    },
     server::config::ServeConfig,
     shared::{request::Request, response::Response, websocket::Websocket},
 };
@@ -47,6 +51,7 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
 
     let submodule_tcp = TableBuilder::new(lua.clone())?
         .with_async_function("connect", net_tcp_connect)?
+        .with_async_function("connectRetry", net_tcp_connect_retry)?
         .with_async_function("host", net_tcp_host)?
         .build_readonly()?;
 
@@ -65,6 +70,8 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         .with_async_function("serve", net_http_serve)?
         .with_function("urlEncode", net_url_encode)?
         .with_function("urlDecode", net_url_decode)?
+        .with_function("parseUrl", net_parse_url)?
+        .with_function("pool", net_pool)?
         .with_value("http", submodule_http)?
         .with_value("tcp", submodule_tcp)?
         .with_value("ws", submodule_ws)?
@@ -90,16 +97,30 @@ async fn net_ws_connect(_: Lua, url: String) -> LuaResult<Websocket<WsStream>> {
     self::client::connect_ws(url).await
 }
 
-async fn net_udp_bind(_: Lua, port: u16) -> LuaResult<Udp> {
-    Udp::bind(port).await
+async fn net_udp_bind(_: Lua, (port, config): (u16, UdpBindConfig)) -> LuaResult<Udp> {
+    Udp::bind(port, config).await
 }
 
 async fn net_udp_connect(_: Lua, (host, port): (String, u16)) -> LuaResult<Udp> {
     Udp::connect(host, port).await
 }
 
-async fn net_tcp_host(_: Lua, (host, port): (String, u16)) -> LuaResult<TcpHost> {
-    TcpHost::new(host, port).await.into_lua_err()
+async fn net_tcp_connect_retry(
+    _: Lua,
+    (host, port, config): (String, u16, TcpRetryConfig),
+) -> LuaResult<Tcp> {
+    self::client::connect_tcp_retry(host, port, config).await
+}
+
+async fn net_tcp_host(
+    _: Lua,
+    (host, port, config): (String, u16, TcpHostConfig),
+) -> LuaResult<TcpHost> {
+    TcpHost::new(host, port, config).await.into_lua_err()
+}
+
+fn net_pool(_: &Lua, config: TcpPoolConfig) -> LuaResult<TcpPool> {
+    Ok(TcpPool::new(config))
 }
 
 fn net_url_encode(
@@ -119,3 +140,10 @@ fn net_url_decode(
     let bytes = self::url::decode(lua_string, as_binary)?;
     lua.create_string(bytes)
 }
+
+fn net_parse_url(lua: &Lua, url: String) -> LuaResult<(LuaValue, Option<String>)> {
+    match self::url::parse(lua.clone(), &url)? {
+        Ok(table) => Ok((LuaValue::Table(table), None)),
+        Err(message) => Ok((LuaValue::Nil, Some(message))),
+    }
+}
@@ -1,12 +1,30 @@
-use async_tungstenite::tungstenite::{error::ProtocolError, handshake::derive_accept_key};
+use async_channel::bounded;
+use async_net::TcpStream;
+use async_tungstenite::{
+    WebSocketStream,
+    tungstenite::{error::ProtocolError, handshake::derive_accept_key, protocol::Role},
+};
 
 use hyper::{
     HeaderMap, Request as HyperRequest, Response as HyperResponse, StatusCode,
     body::Incoming,
     header::{CONNECTION, HeaderName, UPGRADE},
+    server::conn::http1::Builder as Http1Builder,
+    service::service_fn,
+    upgrade::Upgraded,
+};
+
+use mlua::prelude::*;
+
+use crate::{
+    body::ReadableBody,
+    shared::{
+        hyper::{HyperIo, HyperTimer},
+        websocket::Websocket,
+    },
 };
 
-use crate::body::ReadableBody;
+pub type UpgradedWebsocket = Websocket<WebSocketStream<HyperIo<Upgraded>>>;
 
 const SEC_WEBSOCKET_VERSION: HeaderName = HeaderName::from_static("sec-websocket-version");
 const SEC_WEBSOCKET_KEY: HeaderName = HeaderName::from_static("sec-websocket-key");
@@ -54,3 +72,58 @@ pub fn make_upgrade_response(
         .body(ReadableBody::from("switching to websocket protocol"))
         .unwrap())
 }
+
+fn make_bad_request_response(message: impl Into<String>) -> HyperResponse<ReadableBody> {
+    HyperResponse::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header(CONNECTION, "close")
+        .body(ReadableBody::from(message.into()))
+        .unwrap()
+}
+
+/**
+    Performs the server side of the WebSocket handshake on a freshly accepted connection.
+
+    Requests that are not valid WebSocket upgrades are answered with a
+    400 response and closed, in which case this returns `None`.
+*/
+pub async fn upgrade_connection(stream: TcpStream) -> LuaResult<Option<UpgradedWebsocket>> {
+    let (upgrade_tx, upgrade_rx) = bounded(1);
+
+    let service = service_fn(move |request: HyperRequest<Incoming>| {
+        let upgrade_tx = upgrade_tx.clone();
+        async move {
+            if !is_upgrade_request(&request) {
+                return Ok::<_, LuaError>(make_bad_request_response(
+                    "Expected a WebSocket upgrade request",
+                ));
+            }
+            let response = match make_upgrade_response(&request) {
+                Ok(res) => res,
+                Err(err) => return Ok(make_bad_request_response(err.to_string())),
+            };
+            // NOTE: The connection is handed over once this response is written,
+            // so the request is kept around to wait for that after serving ends
+            let _ = upgrade_tx.try_send(request);
+            Ok(response)
+        }
+    });
+
+    Http1Builder::new()
+        .timer(HyperTimer)
+        .keep_alive(true)
+        .serve_connection(HyperIo::from(stream), service)
+        .with_upgrades()
+        .await
+        .into_lua_err()?;
+
+    let Ok(request) = upgrade_rx.try_recv() else {
+        return Ok(None);
+    };
+
+    let upgraded = hyper::upgrade::on(request).await.into_lua_err()?;
+    let stream =
+        WebSocketStream::from_raw_socket(HyperIo::from(upgraded), Role::Server, None).await;
+
+    Ok(Some(Websocket::from(stream)))
+}
@@ -0,0 +1,13 @@
+/**
+    Formats a host and port into an address that can be bound to or resolved.
+
+    IPv6 literals must be wrapped in brackets for the port to be parsed
+    correctly, so they are wrapped here, unless they already are.
+*/
+pub fn format_addr(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
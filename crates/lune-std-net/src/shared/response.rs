@@ -111,8 +111,15 @@ impl FromLua for Response {
                 .transpose()?
                 .unwrap_or_default();
 
-            // Extract body
-            let body = tab.get::<ReadableBody>("body")?;
+            // Extract body, where an array of chunks means a chunked response
+            let body = match tab.get::<LuaValue>("body")? {
+                LuaValue::Table(chunks) => ReadableBody::chunked(
+                    chunks
+                        .sequence_values::<ReadableBody>()
+                        .collect::<LuaResult<Vec<_>>>()?,
+                ),
+                value => ReadableBody::from_lua(value, lua)?,
+            };
 
             // Build the full response
             let mut response = HyperResponse::new(body);
@@ -1,56 +1,422 @@
 use std::{
-    io::Error,
+    io::{Error, ErrorKind},
     net::{IpAddr, SocketAddr},
-    sync::Arc,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
 };
 
-use async_lock::Mutex as AsyncMutex;
-use async_net::TcpListener;
+use async_channel::{Receiver, Sender, unbounded};
+use async_io::Timer;
+use async_lock::{Mutex as AsyncMutex, Semaphore, SemaphoreGuardArc};
+use async_net::{TcpListener, TcpStream};
 use bstr::BString;
 use futures::{
     io::{ReadHalf, WriteHalf},
     prelude::*,
 };
+use lune_std_file::FileObject;
 use mlua::prelude::*;
-
-use crate::client::stream::MaybeTlsStream;
+use mlua_luau_scheduler::{LuaSchedulerExt, LuaSpawnExt};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{
+    client::{pool::TcpPool, stream::MaybeTlsStream},
+    server::upgrade::{UpgradedWebsocket, upgrade_connection},
+    shared::{
+        addr::format_addr,
+        futures::{Either, either},
+    },
+};
 
 const DEFAULT_BUFFER_SIZE: usize = 1024;
+// Largest read made at once when reading a serialized FileObject
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+// Largest serialized FileObject that can be read, lengths in the format are 32-bit
+const MAX_FILE_LEN: usize = u32::MAX as usize;
+// Largest scratch buffer kept around between reads, unless the read buffer size is even larger,
+// larger ones are shrunk back down after use
+const MAX_RETAINED_SCRATCH: usize = 64 * 1024;
+// Same as the backlog used by the standard library listener
+const DEFAULT_BACKLOG: u32 = 128;
+// How long serving waits after a failed accept before accepting again
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+struct TcpReader {
+    stream: ReadHalf<MaybeTlsStream>,
+    // Bytes that have been read from the stream (by peeking) but not yet consumed
+    buffer: Vec<u8>,
+    // Reused by every read from the stream, instead of allocating a new buffer for each of them
+    scratch: Vec<u8>,
+    // Shared with the stream, so that reads of the configured size keep reusing the scratch buffer
+    read_buffer_size: Arc<AtomicUsize>,
+}
+
+impl TcpReader {
+    /**
+        Reads once from the stream into the start of the scratch buffer, returning the number of bytes read.
+
+        The scratch buffer grows to fit the largest read, up to a limit, so
+        repeated reads of the same size do not allocate anything for the read itself.
+    */
+    async fn read_chunk(&mut self, size: usize) -> Result<usize, Error> {
+        if self.scratch.len() < size {
+            self.scratch.resize(size, 0);
+        }
+        self.stream.read(&mut self.scratch[..size]).await
+    }
+
+    // Moves bytes read into the scratch buffer over to the peeked bytes
+    fn keep_chunk(&mut self, read: usize) {
+        self.buffer.extend_from_slice(&self.scratch[..read]);
+        self.shrink_scratch();
+    }
+
+    // A single huge read should not pin its buffer for the rest of the connection
+    fn shrink_scratch(&mut self) {
+        let retained = MAX_RETAINED_SCRATCH.max(self.read_buffer_size.load(Ordering::Relaxed));
+        if self.scratch.len() > retained {
+            self.scratch.truncate(retained);
+            self.scratch.shrink_to_fit();
+        }
+    }
+}
+
+// A connection handed out by a pool, returned to it once released
+#[derive(Debug, Clone)]
+struct PoolLease {
+    pool: TcpPool,
+    released: Arc<AtomicBool>,
+}
 
 #[derive(Debug, Clone)]
 pub struct Tcp {
     local_addr: Arc<Option<SocketAddr>>,
     remote_addr: Arc<Option<SocketAddr>>,
-    read_half: Arc<AsyncMutex<ReadHalf<MaybeTlsStream>>>,
+    raw_fd: Option<i64>,
+    reader: Arc<AsyncMutex<TcpReader>>,
     write_half: Arc<AsyncMutex<WriteHalf<MaybeTlsStream>>>,
+    read_timeout: Arc<Mutex<Option<Duration>>>,
+    // Size of reads made without an explicit size
+    read_buffer_size: Arc<AtomicUsize>,
+    // Slot held in the connection limit of the host that accepted this stream, if any
+    permit: Arc<Mutex<Option<SemaphoreGuardArc>>>,
+    lease: Option<PoolLease>,
 }
 
 impl Tcp {
-    async fn read(&self, size: usize) -> Result<Option<Vec<u8>>, Error> {
-        let mut buf = vec![0; size];
+    /**
+        Hands out this connection from the given pool, as a new handle
+        that returns the connection to the pool when released or closed.
+    */
+    pub(crate) fn leased(self, pool: TcpPool) -> Self {
+        Self {
+            lease: Some(PoolLease {
+                pool,
+                released: Arc::new(AtomicBool::new(false)),
+            }),
+            ..self
+        }
+    }
 
-        loop {
-            let mut handle = self.read_half.lock().await;
-            let read = handle.read(&mut buf).await?;
+    /**
+        Errors once a pooled connection has been released through this handle, since
+        the pool may have handed it to someone else, who must not see our reads and writes.
+    */
+    fn ensure_not_released(&self) -> Result<(), Error> {
+        match &self.lease {
+            Some(lease) if lease.released.load(Ordering::SeqCst) => Err(Error::new(
+                ErrorKind::NotConnected,
+                "connection has been released",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn with_permit(self, permit: Option<SemaphoreGuardArc>) -> Self {
+        *self.permit.lock().expect("permit lock poisoned") = permit;
+        self
+    }
+
+    /**
+        Reads up to `size` bytes, handing them to `on_data` while they are still
+        in the read buffers, so that callers can copy them wherever they need to go.
+    */
+    async fn read<T>(
+        &self,
+        size: usize,
+        on_data: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>, Error> {
+        self.ensure_not_released()?;
+        self.with_read_timeout(self.read_inner(size, on_data)).await
+    }
+
+    async fn peek<T>(
+        &self,
+        size: usize,
+        on_data: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>, Error> {
+        self.ensure_not_released()?;
+        self.with_read_timeout(self.peek_inner(size, on_data)).await
+    }
+
+    async fn with_read_timeout<T>(
+        &self,
+        fut: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let timeout = *self
+            .read_timeout
+            .lock()
+            .expect("read timeout lock poisoned");
+
+        let Some(timeout) = timeout else {
+            return fut.await;
+        };
+
+        /*
+            NOTE: A single read either completes with data or is still pending
+            when the timer fires, and dropping a pending read does not consume
+            anything from the stream, so the next read picks up where we left off -
+            reads made in several steps keep what they got in the peeked bytes
+        */
+        futures_lite::future::or(fut, async move {
+            Timer::after(timeout).await;
+            Err(Error::from(ErrorKind::TimedOut))
+        })
+        .await
+    }
+
+    async fn read_inner<T>(
+        &self,
+        size: usize,
+        on_data: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>, Error> {
+        let mut reader = self.reader.lock().await;
+
+        // Any previously peeked bytes must be consumed before reading more
+        if !reader.buffer.is_empty() {
+            let len = size.min(reader.buffer.len());
+            let value = on_data(&reader.buffer[..len]);
+            reader.buffer.drain(..len);
+            return Ok(Some(value));
+        }
+
+        let read = reader.read_chunk(size).await?;
+
+        if read == 0 {
+            reader.shrink_scratch();
+            return Ok(None);
+        }
+
+        let value = on_data(&reader.scratch[..read]);
+        reader.shrink_scratch();
+        Ok(Some(value))
+    }
+
+    async fn peek_inner<T>(
+        &self,
+        size: usize,
+        on_data: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>, Error> {
+        let mut reader = self.reader.lock().await;
+
+        while reader.buffer.len() < size {
+            let wanted = DEFAULT_BUFFER_SIZE.max(size - reader.buffer.len());
+            let read = reader.read_chunk(wanted).await?;
+
+            if read == 0 {
+                reader.shrink_scratch();
+                break;
+            }
+
+            reader.keep_chunk(read);
+        }
+
+        if reader.buffer.is_empty() {
+            return Ok(None);
+        }
+
+        let len = size.min(reader.buffer.len());
+        Ok(Some(on_data(&reader.buffer[..len])))
+    }
+
+    /**
+        Reads exactly `len` bytes, with the read timeout applying to all of them at once.
+
+        Bytes are kept with the peeked ones until all of them have arrived, so timing out
+        or reaching the end of the stream halfway through does not consume any of them.
+    */
+    async fn read_exact(&self, len: usize) -> Result<Vec<u8>, Error> {
+        self.ensure_not_released()?;
+        self.with_read_timeout(self.read_exact_inner(len)).await
+    }
+
+    async fn read_exact_inner(&self, len: usize) -> Result<Vec<u8>, Error> {
+        let mut reader = self.reader.lock().await;
+
+        // NOTE: The buffer grows as bytes arrive, the length comes
+        // from Lua and must not be allocated before anything is sent
+        while reader.buffer.len() < len {
+            let wanted = (len - reader.buffer.len()).min(FILE_CHUNK_SIZE);
+            let read = reader.read_chunk(wanted).await?;
 
             if read == 0 {
-                return Ok(None);
+                reader.shrink_scratch();
+                return Err(Error::from(ErrorKind::UnexpectedEof));
             }
 
-            if read > 0 {
-                buf.truncate(read);
-                return Ok(Some(buf));
+            reader.keep_chunk(read);
+        }
+
+        let rest = reader.buffer.split_off(len);
+        Ok(std::mem::replace(&mut reader.buffer, rest))
+    }
+
+    pub(crate) async fn is_connected(&self) -> bool {
+        // A read that is already waiting on the stream means we can not check
+        // without stealing its data, and it will see the close by itself anyway
+        let Some(mut reader) = self.reader.try_lock() else {
+            return true;
+        };
+
+        if !reader.buffer.is_empty() {
+            return true;
+        }
+
+        /*
+            NOTE: Polling the read once tries it without waiting, and dropping
+            it while pending does not consume anything from the stream - any
+            bytes that did arrive are kept around for the next read or peek
+        */
+        match futures_lite::future::poll_once(reader.read_chunk(DEFAULT_BUFFER_SIZE)).await {
+            None => true,
+            Some(Ok(0) | Err(_)) => false,
+            Some(Ok(read)) => {
+                reader.keep_chunk(read);
+                true
             }
         }
     }
 
+    /**
+        Checks if the connection is still open and has nothing left to read,
+        so that it can be handed to someone else without leaking any data.
+
+        Bytes that were peeked, left unread, or arrived while idle make the
+        connection unusable for anyone else, since they belong to earlier requests.
+    */
+    pub(crate) async fn is_reusable(&self) -> bool {
+        if !self.is_connected().await {
+            return false;
+        }
+        // A read still waiting on the stream would receive the next user's data
+        self.reader
+            .try_lock()
+            .is_some_and(|reader| reader.buffer.is_empty())
+    }
+
+    fn set_read_timeout(&self, secs: Option<f64>) {
+        // Timeouts too long to represent, such as math.huge, would never fire anyway
+        let timeout = secs
+            .filter(|secs| *secs > 0.0)
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+        *self
+            .read_timeout
+            .lock()
+            .expect("read timeout lock poisoned") = timeout;
+    }
+
+    fn set_read_buffer_size(&self, size: usize) -> LuaResult<()> {
+        if size == 0 {
+            return Err(LuaError::runtime("read buffer size must be at least 1"));
+        }
+        self.read_buffer_size.store(size, Ordering::Relaxed);
+        Ok(())
+    }
+
     async fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+        self.ensure_not_released()?;
         let mut handle = self.write_half.lock().await;
         handle.write_all(&data).await?;
         Ok(())
     }
 
+    /**
+        Writes a file in the same format as `FileObject::serialize`, copying its raw
+        region out in chunks as they are written, instead of serializing it all up front.
+    */
+    async fn write_file(&self, file: &FileObject) -> Result<(), Error> {
+        self.ensure_not_released()?;
+        let (header, raw_len, safe) = file.serialize_parts();
+
+        // Held for the whole file, so that no other writes can end up in the middle of it
+        let mut handle = self.write_half.lock().await;
+        handle.write_all(&header).await?;
+
+        let mut chunk = vec![0; raw_len.min(FILE_CHUNK_SIZE)];
+        let mut offset = 0;
+        while offset < raw_len {
+            let wanted = (raw_len - offset).min(FILE_CHUNK_SIZE);
+            // The file is not locked while writing, so it could have shrunk since the header was written
+            if file.copy_raw(offset, &mut chunk[..wanted]) < wanted {
+                return Err(Error::other("file shrank while it was being written"));
+            }
+            handle.write_all(&chunk[..wanted]).await?;
+            offset += wanted;
+        }
+
+        handle.write_all(&safe).await?;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.ensure_not_released()?;
+        let mut handle = self.write_half.lock().await;
+        handle.flush().await?;
+        Ok(())
+    }
+
     async fn close(&self) -> Result<(), Error> {
+        if self.lease.is_some() {
+            self.release().await
+        } else {
+            self.shutdown().await
+        }
+    }
+
+    /**
+        Returns a pooled connection to its pool, or closes it if it did not come from one.
+
+        Releasing the same handle more than once does nothing, so that
+        the connection is never handed out to two users at once.
+    */
+    async fn release(&self) -> Result<(), Error> {
+        let Some(lease) = &self.lease else {
+            return self.shutdown().await;
+        };
+
+        if lease.released.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // The next user of the connection should not inherit any settings from this one
+        self.set_read_timeout(None);
+        self.read_buffer_size
+            .store(DEFAULT_BUFFER_SIZE, Ordering::Relaxed);
+        let tcp = Self {
+            lease: None,
+            ..self.clone()
+        };
+        lease.pool.release(tcp).await
+    }
+
+    pub(crate) async fn shutdown(&self) -> Result<(), Error> {
+        // Closing frees up the connection slot right away, instead
+        // of waiting for every reference to the stream to be dropped
+        self.permit.lock().expect("permit lock poisoned").take();
         let mut handle = self.write_half.lock().await;
         handle.close().await?;
         Ok(())
@@ -94,13 +460,25 @@ where
 
         let local_addr = stream.local_addr().ok();
         let remote_addr = stream.remote_addr().ok();
+        let raw_fd = stream.raw_fd();
         let (read, write) = stream.split();
+        let read_buffer_size = Arc::new(AtomicUsize::new(DEFAULT_BUFFER_SIZE));
 
         Self {
             local_addr: Arc::new(local_addr),
             remote_addr: Arc::new(remote_addr),
-            read_half: Arc::new(AsyncMutex::new(read)),
+            raw_fd,
+            reader: Arc::new(AsyncMutex::new(TcpReader {
+                stream: read,
+                buffer: Vec::new(),
+                scratch: Vec::new(),
+                read_buffer_size: Arc::clone(&read_buffer_size),
+            })),
             write_half: Arc::new(AsyncMutex::new(write)),
+            read_timeout: Arc::new(Mutex::new(None)),
+            read_buffer_size,
+            permit: Arc::new(Mutex::new(None)),
+            lease: None,
         }
     }
 }
@@ -127,57 +505,321 @@ impl LuaUserData for Tcp {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         methods.add_async_method("read", |lua, this, size: Option<usize>| {
             let this = this.clone();
-            let size = size.unwrap_or(DEFAULT_BUFFER_SIZE);
+            let size = size.unwrap_or_else(|| this.read_buffer_size.load(Ordering::Relaxed));
+            async move {
+                // Created straight from the read buffers, without copying the bytes anywhere else first
+                let result = this.read(size, |bytes| lua.create_string(bytes)).await;
+                read_result_into_lua(result)
+            }
+        });
 
+        methods.add_async_method("peek", |lua, this, size: usize| {
+            let this = this.clone();
             async move {
-                match this.read(size).await.into_lua_err()? {
-                    Some(bytes) => Ok(LuaValue::String(lua.create_string(bytes)?)),
-                    None => Ok(LuaValue::Nil),
-                }
+                let result = this.peek(size, |bytes| lua.create_string(bytes)).await;
+                read_result_into_lua(result)
             }
         });
 
+        methods.add_async_method("isConnected", |_, this, (): ()| {
+            let this = this.clone();
+            async move {
+                this.ensure_not_released().into_lua_err()?;
+                Ok(this.is_connected().await)
+            }
+        });
+
+        methods.add_method("setReadTimeout", |_, this, secs: Option<f64>| {
+            this.ensure_not_released().into_lua_err()?;
+            this.set_read_timeout(secs);
+            Ok(())
+        });
+
+        methods.add_method("setReadBufferSize", |_, this, size: usize| {
+            this.ensure_not_released().into_lua_err()?;
+            this.set_read_buffer_size(size)
+        });
+
         methods.add_async_method("write", |_, this, data: BString| {
             let this = this.clone();
             let data = data.to_vec();
             async move { this.write(data).await.into_lua_err() }
         });
 
+        methods.add_async_method("writev", |_, this, chunks: Vec<BString>| {
+            let this = this.clone();
+            // Joined up front, so the whole message is written under one lock and in as few syscalls as possible
+            let data = chunks.concat();
+            async move {
+                let len = data.len();
+                this.write(data).await.into_lua_err()?;
+                Ok(len)
+            }
+        });
+
+        methods.add_async_method("writeFile", |_, this, file: LuaUserDataRef<FileObject>| {
+            let this = this.clone();
+            let file = file.clone();
+            async move { this.write_file(&file).await.into_lua_err() }
+        });
+
+        methods.add_async_method("readFile", |_, this, len: usize| {
+            let this = this.clone();
+            async move {
+                if len > MAX_FILE_LEN {
+                    return Err(LuaError::runtime(format!(
+                        "file length {len} is larger than the maximum of {MAX_FILE_LEN} bytes"
+                    )));
+                }
+                let data = this.read_exact(len).await.map_err(|e| match e.kind() {
+                    ErrorKind::UnexpectedEof => {
+                        LuaError::runtime("stream closed before the whole file was read")
+                    }
+                    _ => e.into_lua_err(),
+                })?;
+                FileObject::deserialize(&data)
+            }
+        });
+
+        methods.add_async_method("flush", |_, this, (): ()| {
+            let this = this.clone();
+            async move { this.flush().await.into_lua_err() }
+        });
+
         methods.add_async_method("close", |_, this, (): ()| {
             let this = this.clone();
             async move { this.close().await.into_lua_err() }
         });
 
+        methods.add_async_method("release", |_, this, (): ()| {
+            let this = this.clone();
+            async move { this.release().await.into_lua_err() }
+        });
+
         methods.add_method("host", |_, this, ()| Ok(this.host_type()));
+
+        methods.add_method("rawFd", |_, this, ()| {
+            this.ensure_not_released().into_lua_err()?;
+            Ok(this.raw_fd)
+        });
+    }
+}
+
+fn read_result_into_lua(
+    result: Result<Option<LuaResult<LuaString>>, Error>,
+) -> LuaResult<(LuaValue, Option<&'static str>)> {
+    match result {
+        Ok(Some(string)) => Ok((LuaValue::String(string?), None)),
+        Ok(None) => Ok((LuaValue::Nil, None)),
+        Err(e) if e.kind() == ErrorKind::TimedOut => Ok((LuaValue::Nil, Some("timeout"))),
+        Err(e) => Err(e.into_lua_err()),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpHostConfig {
+    pub backlog: Option<u32>,
+    pub max_connections: Option<usize>,
+    pub ipv6_only: Option<bool>,
+}
+
+impl FromLua for TcpHostConfig {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let LuaValue::Nil = value {
+            Ok(TcpHostConfig::default())
+        } else if let LuaValue::Table(tab) = value {
+            let mut this = TcpHostConfig::default();
+
+            if let Some(backlog) = tab.get::<Option<_>>("backlog")? {
+                this.backlog = Some(backlog);
+            }
+            if let Some(max_connections) = tab.get::<Option<usize>>("maxConnections")? {
+                if max_connections == 0 {
+                    return Err(LuaError::runtime("maxConnections must be at least 1"));
+                }
+                this.max_connections = Some(max_connections);
+            }
+            if let Some(ipv6_only) = tab.get::<Option<_>>("ipv6Only")? {
+                this.ipv6_only = Some(ipv6_only);
+            }
+
+            Ok(this)
+        } else {
+            Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("TcpHostConfig"),
+                message: None,
+            })
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct TcpHost {
-    listener: Arc<TcpListener>,
+    listener: Arc<Mutex<Option<Arc<TcpListener>>>>,
     local_addr: SocketAddr,
+    connections: Option<Arc<Semaphore>>,
+    closed_tx: Sender<()>,
+    closed_rx: Receiver<()>,
+    // Web sockets that finished their handshake in the background, see `accept_websocket`
+    websockets_tx: Sender<UpgradedWebsocket>,
+    websockets_rx: Receiver<UpgradedWebsocket>,
 }
 
 impl TcpHost {
-    pub async fn new(addr: String, port: u16) -> Result<Self, Error> {
-        let bind_addr = format!("{addr}:{port}");
-        let listener = TcpListener::bind(&bind_addr).await?;
+    pub async fn new(addr: String, port: u16, config: TcpHostConfig) -> Result<Self, Error> {
+        let bind_addr = format_addr(&addr, port);
+        let listener = if config.backlog.is_some() || config.ipv6_only.is_some() {
+            bind_with_config(&bind_addr, config).await?
+        } else {
+            TcpListener::bind(&bind_addr).await?
+        };
         let local_addr = listener.local_addr()?;
+        let connections = config
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let (closed_tx, closed_rx) = unbounded();
+        let (websockets_tx, websockets_rx) = unbounded();
         Ok(Self {
-            listener: Arc::new(listener),
+            listener: Arc::new(Mutex::new(Some(Arc::new(listener)))),
             local_addr,
+            connections,
+            closed_tx,
+            closed_rx,
+            websockets_tx,
+            websockets_rx,
         })
     }
 
-    async fn accept(&self) -> Result<Tcp, Error> {
-        let (stream, _) = self.listener.accept().await?;
-        Ok(Tcp::from(stream))
+    async fn accept_inner(
+        &self,
+        listener: &TcpListener,
+    ) -> Result<(TcpStream, SocketAddr, Option<SemaphoreGuardArc>), Error> {
+        // Wait for a free connection slot before accepting, any clients
+        // connecting in the meantime are queued up in the listen backlog
+        let permit = match &self.connections {
+            Some(connections) => Some(connections.acquire_arc().await),
+            None => None,
+        };
+        let (stream, addr) = listener.accept().await?;
+        Ok((stream, addr, permit))
+    }
+
+    async fn accept_stream(
+        &self,
+    ) -> Result<(TcpStream, SocketAddr, Option<SemaphoreGuardArc>), Error> {
+        let listener = self
+            .listener
+            .lock()
+            .expect("listener lock poisoned")
+            .clone()
+            .ok_or_else(listener_closed)?;
+
+        // Closing the host wakes up any pending accepts, so
+        // that they stop holding on to the listener socket
+        match either(self.closed_rx.recv(), self.accept_inner(&listener)).await {
+            Either::Left(_) => Err(listener_closed()),
+            Either::Right(result) => result,
+        }
+    }
+
+    async fn accept(&self) -> Result<(Tcp, SocketAddr), Error> {
+        let (stream, addr, permit) = self.accept_stream().await?;
+        Ok((Tcp::from(stream).with_permit(permit), addr))
+    }
+
+    async fn accept_websocket(&self, lua: &Lua) -> LuaResult<UpgradedWebsocket> {
+        loop {
+            // Handshakes run in the background, so that a slow client
+            // does not hold up the ones connecting after it
+            let accepted = match either(self.websockets_rx.recv(), self.accept_stream()).await {
+                Either::Left(Ok(websocket)) => return Ok(websocket),
+                Either::Left(Err(_)) => return Err(listener_closed()).into_lua_err(),
+                Either::Right(accepted) => accepted,
+            };
+
+            // NOTE: The connection slot is only held until the handshake is done
+            let (stream, _, permit) = accepted.into_lua_err()?;
+            let websockets_tx = self.websockets_tx.clone();
+            lua.spawn_local(async move {
+                let _permit = permit;
+                // Clients that fail the handshake are dropped, and never handed out
+                if let Ok(Some(websocket)) = upgrade_connection(stream).await {
+                    let _ = websockets_tx.send(websocket).await;
+                }
+            });
+        }
     }
 
     fn close(&self) -> Result<(), Error> {
-        drop(self.listener.clone());
+        self.listener.lock().expect("listener lock poisoned").take();
+        self.closed_tx.close();
         Ok(())
     }
+
+    fn serve(&self, lua: &Lua, handler: LuaFunction) -> TcpServeHandle {
+        let this = self.clone();
+        let inner_lua = lua.clone();
+        let last_error = Arc::new(Mutex::new(None));
+        let inner_last_error = Arc::clone(&last_error);
+        lua.spawn_local(async move {
+            loop {
+                match this.accept().await {
+                    Ok((client, addr)) => {
+                        // NOTE: Errors in the handler are reported by the scheduler,
+                        // this will only error if the handler could not be spawned
+                        let _ = inner_lua.push_thread_back(
+                            handler.clone(),
+                            (client, addr.ip().to_string(), addr.port()),
+                        );
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotConnected => break,
+                    Err(e) => {
+                        // Kept for the handle to report, instead of printing from inside the library
+                        *inner_last_error.lock().expect("last error lock poisoned") =
+                            Some(e.to_string());
+                        // Errors such as running out of file descriptors tend to persist for
+                        // a while, so wait before retrying instead of spinning on them
+                        Timer::after(ACCEPT_RETRY_DELAY).await;
+                    }
+                }
+            }
+        });
+        TcpServeHandle {
+            host: self.clone(),
+            last_error,
+        }
+    }
+}
+
+async fn bind_with_config(bind_addr: &str, config: TcpHostConfig) -> Result<TcpListener, Error> {
+    let mut last_err = None;
+    for addr in async_net::resolve(bind_addr).await? {
+        match listen_std(addr, config) {
+            Ok(listener) => return TcpListener::try_from(listener),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err
+        .unwrap_or_else(|| Error::new(ErrorKind::InvalidInput, "could not resolve to any address")))
+}
+
+fn listen_std(addr: SocketAddr, config: TcpHostConfig) -> Result<std::net::TcpListener, Error> {
+    let backlog = config.backlog.unwrap_or(DEFAULT_BACKLOG);
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // NOTE: Matches the behavior of the standard library listener on unix
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if let (SocketAddr::V6(_), Some(ipv6_only)) = (addr, config.ipv6_only) {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(i32::try_from(backlog).unwrap_or(i32::MAX))?;
+    Ok(socket.into())
+}
+
+fn listener_closed() -> Error {
+    Error::new(ErrorKind::NotConnected, "listener has been closed")
 }
 
 impl LuaUserData for TcpHost {
@@ -191,11 +833,43 @@ impl LuaUserData for TcpHost {
         methods.add_async_method("accept", |_, this, (): ()| {
             let this = this.clone();
             async move {
-                let client = this.accept().await.into_lua_err()?;
+                let (client, _) = this.accept().await.into_lua_err()?;
                 Ok(client)
             }
         });
 
+        methods.add_async_method("acceptWebSocket", |lua, this, (): ()| {
+            let this = this.clone();
+            async move { this.accept_websocket(&lua).await }
+        });
+
+        methods.add_method("serve", |lua, this, handler: LuaFunction| {
+            Ok(this.serve(lua, handler))
+        });
+
         methods.add_method("close", |_, this, (): ()| this.close().into_lua_err());
     }
 }
+
+#[derive(Clone)]
+pub struct TcpServeHandle {
+    host: TcpHost,
+    // Most recent error that accepting a connection failed with, if any
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+impl LuaUserData for TcpServeHandle {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("lastError", |_, this| {
+            Ok(this
+                .last_error
+                .lock()
+                .expect("last error lock poisoned")
+                .clone())
+        });
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("stop", |_, this, (): ()| this.host.close().into_lua_err());
+    }
+}
@@ -1,73 +1,183 @@
-use std::sync::Arc;
-
+use std::{
+    io::ErrorKind,
+    net::{Ipv6Addr, SocketAddr},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use async_lock::Mutex as AsyncMutex;
 use async_net::UdpSocket;
 use mlua::prelude::*;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::shared::addr::format_addr;
+
+// Large enough to hold any UDP datagram without truncating it
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+// Data of a received datagram, along with the address and port it was sent from
+type Datagram = (LuaString, String, u16);
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UdpBindConfig {
+    pub ipv6_only: Option<bool>,
+}
+
+impl FromLua for UdpBindConfig {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let LuaValue::Nil = value {
+            Ok(UdpBindConfig::default())
+        } else if let LuaValue::Table(tab) = value {
+            Ok(UdpBindConfig {
+                ipv6_only: tab.get::<Option<_>>("ipv6Only")?,
+            })
+        } else {
+            Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("UdpBindConfig"),
+                message: None,
+            })
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Udp {
     socket: Arc<UdpSocket>,
+    // Only sockets created using connect have a default destination for send
+    connected: bool,
+    // Reused across receives, the lock also serializes concurrent receives on the socket
+    recv_buffer: Arc<AsyncMutex<Box<[u8]>>>,
+    // Whether recv returns right away when no datagram is available, instead of waiting
+    nonblocking: Arc<AtomicBool>,
 }
 
 impl Udp {
-    pub async fn bind(port: u16) -> LuaResult<Self> {
-        let addr = format!("0.0.0.0:{port}");
-
-        let socket = UdpSocket::bind(addr).await.map_err(LuaError::external)?;
-
-        Ok(Self {
+    fn new(socket: UdpSocket, connected: bool) -> Self {
+        Self {
             socket: Arc::new(socket),
-        })
+            connected,
+            recv_buffer: Arc::new(AsyncMutex::new(vec![0u8; MAX_DATAGRAM_SIZE].into())),
+            nonblocking: Arc::new(AtomicBool::new(false)),
+        }
     }
 
-    pub async fn connect(host: String, port: u16) -> LuaResult<Self> {
-        let addr = format!("{host}:{port}");
+    async fn recv(&self, lua: &Lua) -> LuaResult<Datagram> {
+        let mut buf = self.recv_buffer.lock().await;
 
-        let socket = UdpSocket::bind("0.0.0.0:0")
+        let (len, addr) = self
+            .socket
+            .recv_from(&mut buf)
             .await
             .map_err(LuaError::external)?;
 
+        let data = lua.create_string(&buf[..len])?;
+
+        Ok((data, addr.ip().to_string(), addr.port()))
+    }
+
+    async fn try_recv(&self, lua: &Lua) -> LuaResult<Option<Datagram>> {
+        // Another receive is already waiting, so any datagram that arrives is going to it
+        let Some(mut buf) = self.recv_buffer.try_lock() else {
+            return Ok(None);
+        };
+
+        /*
+            NOTE: The socket itself is always in nonblocking mode, and the first poll
+            of a receive tries it right away, only waiting if it would have blocked
+        */
+        let (len, addr) =
+            match futures_lite::future::poll_once(self.socket.recv_from(&mut buf)).await {
+                None => return Ok(None),
+                Some(Err(e)) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Some(Err(e)) => return Err(LuaError::external(e)),
+                Some(Ok(received)) => received,
+            };
+
+        let data = lua.create_string(&buf[..len])?;
+
+        Ok(Some((data, addr.ip().to_string(), addr.port())))
+    }
+
+    pub async fn bind(port: u16, config: UdpBindConfig) -> LuaResult<Self> {
+        // Binding to IPv6 is opt-in, since dual-stack support varies between platforms
+        let socket = match config.ipv6_only {
+            Some(ipv6_only) => bind_ipv6(port, ipv6_only).map_err(LuaError::external)?,
+            None => UdpSocket::bind(format!("0.0.0.0:{port}"))
+                .await
+                .map_err(LuaError::external)?,
+        };
+
+        Ok(Self::new(socket, false))
+    }
+
+    pub async fn connect(host: String, port: u16) -> LuaResult<Self> {
+        let addr = async_net::resolve(format_addr(&host, port))
+            .await
+            .map_err(LuaError::external)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| LuaError::external("could not resolve to any address"))?;
+
+        // The local socket must be of the same address family as the remote host
+        let local = match addr {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+
+        let socket = UdpSocket::bind(local).await.map_err(LuaError::external)?;
+
         socket.connect(addr).await.map_err(LuaError::external)?;
 
-        Ok(Self {
-            socket: Arc::new(socket),
-        })
+        Ok(Self::new(socket, true))
     }
 }
 
 impl LuaUserData for Udp {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         methods.add_async_method("send", |_, this, data: LuaString| async move {
+            if !this.connected {
+                return Err(LuaError::external("socket is not connected; use sendTo"));
+            }
+
             let bytes = data.as_bytes();
-            this.socket.send(&bytes).await.map_err(LuaError::external)?;
-            Ok(())
+            let sent = this.socket.send(&bytes).await.map_err(LuaError::external)?;
+            Ok(sent)
         });
 
         methods.add_async_method(
             "sendTo",
             |_, this, (data, host, port): (LuaString, String, u16)| async move {
-                let addr = format!("{host}:{port}");
+                let addr = format_addr(&host, port);
                 let bytes = data.as_bytes();
 
-                this.socket
+                let sent = this
+                    .socket
                     .send_to(&bytes, addr)
                     .await
                     .map_err(LuaError::external)?;
-                Ok(())
+                Ok(sent)
             },
         );
 
         methods.add_async_method("recv", |lua, this, ()| async move {
-            let mut buf = vec![0u8; 65535];
-
-            let (len, addr) = this
-                .socket
-                .recv_from(&mut buf)
-                .await
-                .map_err(LuaError::external)?;
+            let datagram = if this.nonblocking.load(Ordering::Acquire) {
+                this.try_recv(&lua).await?
+            } else {
+                Some(this.recv(&lua).await?)
+            };
+            Ok(unzip_datagram(datagram))
+        });
 
-            let data = lua.create_string(&buf[..len])?;
+        methods.add_async_method("tryRecv", |lua, this, ()| async move {
+            Ok(unzip_datagram(this.try_recv(&lua).await?))
+        });
 
-            Ok((data, addr.ip().to_string(), addr.port()))
+        methods.add_method("setNonblocking", |_, this, nonblocking: bool| {
+            this.nonblocking.store(nonblocking, Ordering::Release);
+            Ok(())
         });
 
         methods.add_method("localAddr", |_, this, ()| {
@@ -78,3 +188,19 @@ impl LuaUserData for Udp {
         methods.add_method("close", |_, _this, ()| Ok(()));
     }
 }
+
+// Datagrams are returned to Lua as separate values, which are all nil when none was received
+fn unzip_datagram(datagram: Option<Datagram>) -> (Option<LuaString>, Option<String>, Option<u16>) {
+    match datagram {
+        Some((data, host, port)) => (Some(data), Some(host), Some(port)),
+        None => (None, None, None),
+    }
+}
+
+fn bind_ipv6(port: u16, ipv6_only: bool) -> std::io::Result<UdpSocket> {
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(ipv6_only)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::try_from(std::net::UdpSocket::from(socket))
+}
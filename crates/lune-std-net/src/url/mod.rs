@@ -1,5 +1,7 @@
 mod decode;
 mod encode;
+mod parse;
 
 pub use self::decode::decode;
 pub use self::encode::encode;
+pub use self::parse::parse;
@@ -0,0 +1,32 @@
+use lune_utils::TableBuilder;
+use mlua::prelude::*;
+use url::Url;
+
+/**
+    Parses the given string as a URL into a table of its components.
+
+    The outer result is for Lua errors, the inner result
+    contains a message if the string was not a valid URL.
+*/
+pub fn parse(lua: Lua, input: &str) -> LuaResult<Result<LuaTable, String>> {
+    let url = match Url::parse(input) {
+        Ok(url) => url,
+        Err(e) => return Ok(Err(format!("Invalid url - {e}"))),
+    };
+
+    // Decode the path for convenience, but fall back to
+    // the raw path if the result would not be valid utf-8
+    let path = urlencoding::decode(url.path())
+        .map_or_else(|_| url.path().to_string(), std::borrow::Cow::into_owned);
+
+    let table = TableBuilder::new(lua)?
+        .with_value("scheme", url.scheme())?
+        .with_value("host", url.host_str())?
+        .with_value("port", url.port_or_known_default())?
+        .with_value("path", path)?
+        .with_value("query", url.query())?
+        .with_value("fragment", url.fragment())?
+        .build()?;
+
+    Ok(Ok(table))
+}
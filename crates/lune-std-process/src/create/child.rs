@@ -1,23 +1,41 @@
-use std::process::ExitStatus;
+use std::{
+    future::{Future, poll_fn},
+    pin::pin,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+    task::{Poll, ready},
+};
 
 use async_channel::{Receiver, Sender, unbounded};
 use async_process::Child as AsyncChild;
 use futures_util::{FutureExt, select};
 
 use mlua::prelude::*;
-use mlua_luau_scheduler::LuaSpawnExt;
+use mlua_luau_scheduler::{LuaSchedulerExt, LuaSpawnExt};
 
 use lune_utils::TableBuilder;
 
-use super::{ChildReader, ChildWriter};
+use super::{ChildReader, ChildWriter, Signal};
+
+// Whether the child process has exited, and its exit status if it could be read
+#[derive(Debug, Clone, Copy)]
+enum ChildState {
+    Running,
+    Exited(Option<ExitStatus>),
+}
 
 #[derive(Debug, Clone)]
 pub struct Child {
     stdin: ChildWriter,
     stdout: ChildReader,
     stderr: ChildReader,
+    #[cfg(unix)]
+    pid: u32,
     kill_tx: Sender<()>,
-    status_rx: Receiver<Option<ExitStatus>>,
+    // Nothing is ever sent, the channel closes once the status has been set
+    exited_rx: Receiver<()>,
+    // Set once the child has exited, and locked while it is being reaped, see `reap_child`
+    state: Arc<Mutex<ChildState>>,
 }
 
 impl Child {
@@ -25,20 +43,82 @@ impl Child {
         let stdin = ChildWriter::from(child.stdin.take());
         let stdout = ChildReader::from(child.stdout.take());
         let stderr = ChildReader::from(child.stderr.take());
+        #[cfg(unix)]
+        let pid = child.id();
 
-        // NOTE: Kill channel is zero size, status is very small
-        // and implements Copy, unbounded will be just fine here
+        // NOTE: Kill and exit channels are zero size, unbounded will be just fine here
         let (kill_tx, kill_rx) = unbounded();
-        let (status_tx, status_rx) = unbounded();
-        lua.spawn(handle_child(child, kill_rx, status_tx)).detach();
+        let (exited_tx, exited_rx) = unbounded();
+        let state = Arc::new(Mutex::new(ChildState::Running));
+        lua.spawn(handle_child(child, kill_rx, exited_tx, Arc::clone(&state)))
+            .detach();
 
         Self {
             stdin,
             stdout,
             stderr,
+            #[cfg(unix)]
+            pid,
             kill_tx,
-            status_rx,
+            exited_rx,
+            state,
+        }
+    }
+
+    /**
+        Waits for the child to exit and returns its exit status.
+
+        The status is only ever set once by the child handler, which then closes
+        the exit channel, waking up any number of waiters to observe the same status.
+    */
+    async fn wait_status(&self) -> Option<ExitStatus> {
+        let _ = self.exited_rx.recv().await;
+        match self.state() {
+            ChildState::Exited(status) => status,
+            ChildState::Running => None,
+        }
+    }
+
+    fn state(&self) -> ChildState {
+        *self.state.lock().expect("state lock poisoned")
+    }
+
+    /**
+        Sends a signal to the child, returning false if it has already exited.
+    */
+    #[cfg(unix)]
+    fn signal(&self, signal: &Signal) -> LuaResult<bool> {
+        let signal = signal.number()?;
+
+        // Held until the signal has been sent, so that the child can not be reaped in the meantime
+        let state = self.state.lock().expect("state lock poisoned");
+        if let ChildState::Exited(_) = *state {
+            return Ok(false);
+        }
+
+        let pid = libc::pid_t::try_from(self.pid).into_lua_err()?;
+        // SAFETY: kill has no memory safety requirements. The pid belongs to our own child
+        // process, which is only ever reaped while holding the state lock, and the state is
+        // set before that lock is released, so the pid can not have been reused by anything else
+        if unsafe { libc::kill(pid, signal) } == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ESRCH) {
+                return Ok(false);
+            }
+            return Err(err.into_lua_err());
+        }
+
+        Ok(true)
+    }
+
+    #[cfg(not(unix))]
+    fn signal(&self, signal: &Signal) -> LuaResult<bool> {
+        signal.ensure_termination()?;
+        if let ChildState::Exited(_) = self.state() {
+            return Ok(false);
         }
+        let _ = self.kill_tx.try_send(());
+        Ok(true)
     }
 }
 
@@ -54,33 +134,88 @@ impl LuaUserData for Child {
             let _ = this.kill_tx.try_send(());
             Ok(())
         });
+        methods.add_method("signal", |_, this, signal: Signal| this.signal(&signal));
         methods.add_async_method("status", |lua, this, (): ()| {
-            let rx = this.status_rx.clone();
+            let this = this.clone();
             async move {
-                let status = rx.recv().await.ok().flatten();
-                let code = status.and_then(|c| c.code()).unwrap_or(9);
+                let status = this.wait_status().await;
+                let code = exit_code(status);
                 TableBuilder::new(lua.clone())?
                     .with_value("ok", code == 0)?
                     .with_value("code", code)?
                     .build_readonly()
             }
         });
+        methods.add_method("tryWait", |_, this, (): ()| {
+            // Only set once the child has exited, so this never waits
+            Ok(match this.state() {
+                ChildState::Exited(status) => Some(exit_code(status)),
+                ChildState::Running => None,
+            })
+        });
+        methods.add_method("onExit", |lua, this, callback: LuaFunction| {
+            let this = this.clone();
+            let inner_lua = lua.clone();
+            lua.spawn_local(async move {
+                let status = this.wait_status().await;
+                let code = exit_code(status);
+                let signal = exit_signal(status);
+                // NOTE: Errors in the callback are reported by the scheduler,
+                // this will only error if the callback could not be spawned
+                let _ = inner_lua.push_thread_back(callback, (code, signal));
+            });
+            Ok(())
+        });
     }
 }
 
+fn exit_code(status: Option<ExitStatus>) -> i32 {
+    status.and_then(|c| c.code()).unwrap_or(9)
+}
+
+#[cfg(unix)]
+fn exit_signal(status: Option<ExitStatus>) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.and_then(|s| s.signal())
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_: Option<ExitStatus>) -> Option<i32> {
+    None
+}
+
 async fn handle_child(
     mut child: AsyncChild,
     kill_rx: Receiver<()>,
-    status_tx: Sender<Option<ExitStatus>>,
+    exited_tx: Sender<()>,
+    state: Arc<Mutex<ChildState>>,
 ) {
-    let status = select! {
-        s = child.status().fuse() => s.ok(), // FUTURE: Propagate this error somehow?
+    select! {
+        () = reap_child(&mut child, &state).fuse() => {},
         _ = kill_rx.recv().fuse() => {
             let _ = child.kill(); // Will only error if already killed
-            None
+            // NOTE: Set before the child is dropped below, which is when it gets reaped
+            *state.lock().expect("state lock poisoned") = ChildState::Exited(None);
         }
-    };
+    }
+
+    exited_tx.close();
+}
+
+/**
+    Waits for the child to exit, and sets its state to exited.
 
-    // Will only error if there are no receivers waiting for the status
-    let _ = status_tx.send(status).await;
+    The child is reaped while polling for its status, which frees up its pid to
+    be reused, so every poll holds the state lock, and the state is set before
+    releasing it. Sending signals holds the same lock, and checks the state first.
+*/
+async fn reap_child(child: &mut AsyncChild, state: &Mutex<ChildState>) {
+    let mut status = pin!(child.status());
+    poll_fn(|cx| {
+        let mut state = state.lock().expect("state lock poisoned");
+        let status = ready!(status.as_mut().poll(cx));
+        *state = ChildState::Exited(status.ok()); // FUTURE: Propagate this error somehow?
+        Poll::Ready(())
+    })
+    .await;
 }
@@ -0,0 +1,84 @@
+use mlua::prelude::*;
+
+/**
+    A signal given from Lua, either by name, with or without the `SIG` prefix, or by number.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signal {
+    Name(String),
+    Number(i32),
+}
+
+impl FromLua for Signal {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::String(s) => {
+                let name = s.to_str()?.trim().to_ascii_uppercase();
+                let name = name.strip_prefix("SIG").unwrap_or(&name);
+                Ok(Self::Name(name.to_string()))
+            }
+            LuaValue::Integer(_) | LuaValue::Number(_) => {
+                Ok(Self::Number(i32::from_lua(value, lua)?))
+            }
+            _ => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "Signal".to_string(),
+                message: Some(format!(
+                    "Invalid signal - expected string or number, got {}",
+                    value.type_name()
+                )),
+            }),
+        }
+    }
+}
+
+#[cfg(unix)]
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("WINCH", libc::SIGWINCH),
+];
+
+impl Signal {
+    /**
+        Gets the signal number to send using `kill`.
+    */
+    #[cfg(unix)]
+    pub fn number(&self) -> LuaResult<i32> {
+        match self {
+            Self::Number(n) => Ok(*n),
+            Self::Name(name) => SIGNALS
+                .iter()
+                .find(|(known, _)| known == name)
+                .map(|(_, n)| *n)
+                .ok_or_else(|| LuaError::runtime(format!("Unknown signal 'SIG{name}'"))),
+        }
+    }
+
+    /**
+        Makes sure the signal is one that terminates the process, which
+        is the only kind of signal that is supported on other platforms.
+    */
+    #[cfg(not(unix))]
+    pub fn ensure_termination(&self) -> LuaResult<()> {
+        match self {
+            Self::Name(name) if name == "TERM" || name == "KILL" => Ok(()),
+            Self::Number(9 | 15) => Ok(()),
+            Self::Name(name) => Err(LuaError::runtime(format!(
+                "Signal 'SIG{name}' is not supported on this platform"
+            ))),
+            Self::Number(n) => Err(LuaError::runtime(format!(
+                "Signal {n} is not supported on this platform"
+            ))),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::rc::Rc;
+
+use async_channel::{Sender, unbounded};
+use async_lock::OnceCell;
+use async_process::Child;
+
+use mlua::prelude::*;
+use mlua_luau_scheduler::LuaSpawnExt;
+
+use crate::options::{ProcessOutputCallbacks, ProcessSpawnOptionsStdioKind};
+
+/**
+    A handle to a child process started using `process.execAsync`.
+
+    The child process runs in the background, and its result
+    can be waited for or the child cancelled using this handle.
+*/
+#[derive(Debug, Clone)]
+pub struct ExecHandle {
+    cancel_tx: Sender<()>,
+    result: Rc<OnceCell<LuaResult<LuaTable>>>,
+}
+
+impl ExecHandle {
+    pub fn new(
+        lua: &Lua,
+        child: Child,
+        stdin: Option<Vec<u8>>,
+        stdout: ProcessSpawnOptionsStdioKind,
+        stderr: ProcessSpawnOptionsStdioKind,
+        output: ProcessOutputCallbacks,
+    ) -> Self {
+        let (cancel_tx, cancel_rx) = unbounded();
+        let result = Rc::new(OnceCell::new());
+
+        lua.spawn_local({
+            let lua = lua.clone();
+            let result = Rc::clone(&result);
+            async move {
+                let res =
+                    super::exec(lua, child, stdin, stdout, stderr, output, Some(cancel_rx)).await;
+                let _ = result.set(res).await;
+            }
+        });
+
+        Self { cancel_tx, result }
+    }
+}
+
+impl LuaUserData for ExecHandle {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("cancel", |_, this, (): ()| {
+            // Will only error if the child process has already finished
+            let _ = this.cancel_tx.try_send(());
+            Ok(())
+        });
+        methods.add_async_method("result", |_, this, (): ()| {
+            let this = this.clone();
+            async move { this.result.wait().await.clone() }
+        });
+    }
+}
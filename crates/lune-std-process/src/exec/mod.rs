@@ -1,3 +1,4 @@
+use async_channel::Receiver;
 use async_process::Child;
 use futures_lite::prelude::*;
 
@@ -5,19 +6,24 @@ use mlua::prelude::*;
 
 use lune_utils::TableBuilder;
 
-use super::options::ProcessSpawnOptionsStdioKind;
+use super::options::{ProcessOutputCallbacks, ProcessSpawnOptionsStdioKind};
 
+mod handle;
 mod tee_writer;
 mod wait_for_child;
 
 use self::wait_for_child::wait_for_child;
 
+pub use self::handle::ExecHandle;
+
 pub async fn exec(
     lua: Lua,
     mut child: Child,
     stdin: Option<Vec<u8>>,
     stdout: ProcessSpawnOptionsStdioKind,
     stderr: ProcessSpawnOptionsStdioKind,
+    output: ProcessOutputCallbacks,
+    cancel: Option<Receiver<()>>,
 ) -> LuaResult<LuaTable> {
     // Write to stdin before anything else - if we got it
     if let Some(stdin) = stdin {
@@ -25,7 +31,7 @@ pub async fn exec(
         child_stdin.write_all(&stdin).await.into_lua_err()?;
     }
 
-    let res = wait_for_child(child, stdout, stderr).await?;
+    let res = wait_for_child(&lua, child, stdout, stderr, &output, cancel).await?;
 
     /*
         NOTE: If an exit code was not given by the child process,
@@ -43,8 +49,9 @@ pub async fn exec(
     let stdout = lua.create_string(&res.stdout)?;
     let stderr = lua.create_string(&res.stderr)?;
     TableBuilder::new(lua)?
-        .with_value("ok", code == 0)?
+        .with_value("ok", code == 0 && !res.cancelled)?
         .with_value("code", code)?
+        .with_value("cancelled", res.cancelled)?
         .with_value("stdout", stdout)?
         .with_value("stderr", stderr)?
         .build_readonly()
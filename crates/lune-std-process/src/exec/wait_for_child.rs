@@ -2,30 +2,44 @@ use std::{io::stdout, process::ExitStatus};
 
 use mlua::prelude::*;
 
+use async_channel::Receiver;
 use async_process::Child;
 use blocking::Unblock;
-use futures_lite::{io, prelude::*};
+use futures_lite::{future, io, prelude::*};
 use futures_util::try_join;
 
 use super::tee_writer::AsyncTeeWriter;
-use crate::options::ProcessSpawnOptionsStdioKind;
+use crate::options::{ProcessOutputCallbacks, ProcessSpawnOptionsStdioKind};
+
+// Largest chunk of output given to an output callback at once
+const CALLBACK_CHUNK_SIZE: usize = 8192;
 
 #[derive(Debug, Clone)]
 pub(super) struct WaitForChildResult {
     pub status: ExitStatus,
     pub stdout: Vec<u8>,
     pub stderr: Vec<u8>,
+    pub cancelled: bool,
 }
 
 async fn read_with_stdio_kind<R>(
+    lua: &Lua,
     read_from: Option<R>,
     kind: ProcessSpawnOptionsStdioKind,
+    callback: Option<&LuaFunction>,
+    retain: bool,
 ) -> LuaResult<Vec<u8>>
 where
     R: AsyncRead + Unpin,
 {
+    if callback.is_some() || !retain {
+        return read_with_callback(lua, read_from, kind, callback, retain).await;
+    }
+
     Ok(match kind {
-        ProcessSpawnOptionsStdioKind::None | ProcessSpawnOptionsStdioKind::Forward => Vec::new(),
+        ProcessSpawnOptionsStdioKind::None
+        | ProcessSpawnOptionsStdioKind::Forward
+        | ProcessSpawnOptionsStdioKind::Terminal => Vec::new(),
         ProcessSpawnOptionsStdioKind::Default => {
             let mut read_from =
                 read_from.expect("read_from must be Some when stdio kind is Default");
@@ -50,23 +64,111 @@ where
     })
 }
 
+/**
+    Reads the stream in chunks, giving each chunk to the callback as soon as it
+    arrives, instead of only once the stream has been read all the way through.
+*/
+async fn read_with_callback<R>(
+    lua: &Lua,
+    read_from: Option<R>,
+    kind: ProcessSpawnOptionsStdioKind,
+    callback: Option<&LuaFunction>,
+    retain: bool,
+) -> LuaResult<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    let Some(mut read_from) = read_from else {
+        return Ok(Vec::new());
+    };
+
+    let mut forward = match kind {
+        ProcessSpawnOptionsStdioKind::Inherit => Some(Unblock::new(stdout())),
+        _ => None,
+    };
+
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; CALLBACK_CHUNK_SIZE];
+    loop {
+        let read = read_from.read(&mut chunk).await.into_lua_err()?;
+        if read == 0 {
+            break;
+        }
+
+        let bytes = &chunk[..read];
+        if let Some(forward) = forward.as_mut() {
+            forward.write_all(bytes).await.into_lua_err()?;
+        }
+        if let Some(callback) = callback {
+            callback.call_async::<()>(lua.create_string(bytes)?).await?;
+        }
+        if retain {
+            buffer.extend_from_slice(bytes);
+        }
+    }
+
+    if let Some(mut forward) = forward {
+        forward.flush().await.into_lua_err()?;
+    }
+
+    Ok(buffer)
+}
+
 pub(super) async fn wait_for_child(
+    lua: &Lua,
     mut child: Child,
     stdout_kind: ProcessSpawnOptionsStdioKind,
     stderr_kind: ProcessSpawnOptionsStdioKind,
+    output: &ProcessOutputCallbacks,
+    cancel: Option<Receiver<()>>,
 ) -> LuaResult<WaitForChildResult> {
     let stdout_opt = child.stdout.take();
     let stderr_opt = child.stderr.take();
 
-    let (status, stdout, stderr) = try_join!(
-        async { child.status().await.into_lua_err() },
-        read_with_stdio_kind(stdout_opt, stdout_kind),
-        read_with_stdio_kind(stderr_opt, stderr_kind)
+    let cancelled = async {
+        // NOTE: The sender being dropped means nobody can cancel anymore,
+        // which should not be treated the same as an explicit cancellation
+        match cancel {
+            Some(rx) if rx.recv().await.is_ok() => {}
+            _ => future::pending().await,
+        }
+    };
+
+    let ((status, cancelled), stdout, stderr) = try_join!(
+        async {
+            let status = async { Some(child.status().await) }
+                .or(async {
+                    cancelled.await;
+                    None
+                })
+                .await;
+            if let Some(status) = status {
+                Ok((status.into_lua_err()?, false))
+            } else {
+                let _ = child.kill(); // Will only error if already exited
+                Ok((child.status().await.into_lua_err()?, true))
+            }
+        },
+        read_with_stdio_kind(
+            lua,
+            stdout_opt,
+            stdout_kind,
+            output.on_stdout.as_ref(),
+            output.retain
+        ),
+        read_with_stdio_kind(
+            lua,
+            stderr_opt,
+            stderr_kind,
+            output.on_stderr.as_ref(),
+            output.retain
+        )
     )?;
 
     Ok(WaitForChildResult {
         status,
         stdout,
         stderr,
+        cancelled,
     })
 }
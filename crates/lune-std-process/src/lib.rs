@@ -7,6 +7,10 @@ use std::{
     process::Stdio,
 };
 
+use async_io::Timer;
+use async_process::Child;
+use futures_lite::prelude::*;
+use futures_util::try_join;
 use mlua::prelude::*;
 use mlua_luau_scheduler::Functions;
 
@@ -16,11 +20,16 @@ use lune_utils::{
     process::{ProcessArgs, ProcessEnv},
 };
 
+use lune_std_serde::{EncodeDecodeFormat, decode};
+
 mod create;
 mod exec;
 mod options;
 
-use self::options::ProcessSpawnOptions;
+use self::options::{
+    ProcessCommand, ProcessOutputCallbacks, ProcessRetryOptions, ProcessSpawnOptions,
+    ProcessSpawnOptionsStdioKind, default_shell,
+};
 
 const TYPEDEFS: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/types.d.luau"));
 
@@ -29,19 +38,22 @@ pub fn typedefs() -> String {
     TYPEDEFS.to_string()
 }
 
-fn load_dotenv_into_table(_: &Lua, env_table: &LuaTable) -> LuaResult<()> {
+/**
+    Reads the variables from the dotenv file in the current directory, in order.
+
+    Missing or unreadable files have no variables, so that
+    scripts can run the same way with or without one.
+*/
+fn read_dotenv() -> Vec<(String, String)> {
     let cwd = get_current_dir();
     let dotenv_path: PathBuf = cwd.join(".env");
 
-    if !dotenv_path.exists() {
-        return Ok(());
-    }
-
     let contents = match fs::read_to_string(&dotenv_path) {
         Ok(c) => c,
-        Err(_) => return Ok(()),
+        Err(_) => return Vec::new(),
     };
 
+    let mut vars = Vec::new();
     for line in contents.lines() {
         let line = line.trim();
 
@@ -55,14 +67,96 @@ fn load_dotenv_into_table(_: &Lua, env_table: &LuaTable) -> LuaResult<()> {
             let value = value.trim();
 
             if !key.is_empty() {
-                env_table.set(key, value)?;
+                vars.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    vars
+}
+
+fn load_dotenv_into_table(_: &Lua, env_table: &LuaTable) -> LuaResult<()> {
+    for (key, value) in read_dotenv() {
+        env_table.set(key, value)?;
+    }
+    Ok(())
+}
+
+/**
+    Makes lookups and assignments in the given environment table ignore the case
+    of keys, matching how environment variables behave on Windows.
+
+    Keys keep the casing they were first set with, so that the table can
+    still be passed along to child processes with correctly-cased variables.
+*/
+fn make_env_case_insensitive(lua: &Lua, env_table: &LuaTable) -> LuaResult<()> {
+    fn find_key(env_table: &LuaTable, key: &LuaString) -> LuaResult<Option<LuaValue>> {
+        for pair in env_table.pairs::<LuaValue, LuaValue>() {
+            let (existing, _) = pair?;
+            if let LuaValue::String(s) = &existing
+                && s.as_bytes().eq_ignore_ascii_case(&key.as_bytes())
+            {
+                return Ok(Some(existing));
             }
         }
+        Ok(None)
     }
 
+    let index = lua.create_function(|_, (tab, key): (LuaTable, LuaValue)| {
+        let LuaValue::String(key) = key else {
+            return Ok(LuaValue::Nil);
+        };
+        match find_key(&tab, &key)? {
+            Some(existing) => tab.raw_get(existing),
+            None => Ok(LuaValue::Nil),
+        }
+    })?;
+
+    let newindex = lua.create_function(|_, (tab, key, val): (LuaTable, LuaValue, LuaValue)| {
+        let existing = match &key {
+            LuaValue::String(s) => find_key(&tab, s)?,
+            _ => None,
+        };
+        tab.raw_set(existing.unwrap_or(key), val)
+    })?;
+
+    let metatable = TableBuilder::new(lua.clone())?
+        .with_value("__index", index)?
+        .with_value("__newindex", newindex)?
+        .build()?;
+
+    env_table.set_metatable(Some(metatable))?;
     Ok(())
 }
 
+/**
+    Creates a table with the environment variables of this process,
+    which are also the ones that child processes inherit.
+*/
+fn create_inherited_env_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let env_table = lua
+        .app_data_ref::<ProcessEnv>()
+        .ok_or_else(|| LuaError::runtime("Missing process env in Lua app data"))?
+        .into_plain_lua_table(lua.clone())?;
+
+    if cfg!(windows) {
+        make_env_case_insensitive(lua, &env_table)?;
+    }
+
+    Ok(env_table)
+}
+
+/**
+    Creates a table with the environment variables of this process,
+    followed by the ones from the dotenv file in the current directory.
+*/
+fn create_env_table(lua: &Lua) -> LuaResult<LuaTable> {
+    // Case-insensitive before loading the dotenv file, so that its keys are normalized too
+    let env_table = create_inherited_env_table(lua)?;
+    load_dotenv_into_table(lua, &env_table)?;
+    Ok(env_table)
+}
+
 /**
     Creates the `process` standard library module.
 
@@ -94,12 +188,7 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         .ok_or_else(|| LuaError::runtime("Missing process args in Lua app data"))?
         .into_plain_lua_table(lua.clone())?;
 
-    let process_env = lua
-        .app_data_ref::<ProcessEnv>()
-        .ok_or_else(|| LuaError::runtime("Missing process env in Lua app data"))?
-        .into_plain_lua_table(lua.clone())?;
-
-    load_dotenv_into_table(&lua, &process_env)?;
+    let process_env = create_env_table(&lua)?;
 
     process_args.set_readonly(true);
 
@@ -115,20 +204,135 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         .with_value("env", process_env)?
         .with_value("exit", process_exit)?
         .with_async_function("exec", process_exec)?
+        .with_async_function("execRetry", process_exec_retry)?
+        .with_async_function("execJson", process_exec_json)?
+        .with_function("execAsync", process_exec_async)?
         .with_function("create", process_create)?
+        .with_async_function("pipe", process_pipe)?
+        .with_async_function("shell", process_shell)?
+        .with_function("resolveEnv", process_resolve_env)?
         .build_readonly()
 }
 
+fn process_resolve_env(lua: &Lua, options: ProcessSpawnOptions) -> LuaResult<LuaTable> {
+    let env_table = create_inherited_env_table(lua)?;
+    for (key, value) in options.envs {
+        env_table.set(key, value)?;
+    }
+    Ok(env_table)
+}
+
 async fn process_exec(
     lua: Lua,
-    (program, args, mut options): (String, ProcessArgs, ProcessSpawnOptions),
+    (program, args, options): (String, ProcessArgs, LuaValue),
 ) -> LuaResult<LuaTable> {
+    // Output callbacks live in the same table as the spawn options
+    let output = ProcessOutputCallbacks::from_lua(options.clone(), &lua)?;
+    let options = ProcessSpawnOptions::from_lua(options, &lua)?;
+    exec_once(lua, program, args, options, output).await
+}
+
+async fn exec_once(
+    lua: Lua,
+    program: String,
+    args: ProcessArgs,
+    options: ProcessSpawnOptions,
+    output: ProcessOutputCallbacks,
+) -> LuaResult<LuaTable> {
+    let (child, stdin, stdout, stderr) = spawn_exec_child(program, args, options)?;
+    exec::exec(lua, child, stdin, stdout, stderr, output, None).await
+}
+
+async fn process_exec_retry(
+    lua: Lua,
+    (program, args, options): (String, ProcessArgs, LuaValue),
+) -> LuaResult<(LuaTable, u32)> {
+    // Retry options live in the same table as the spawn options
+    let retry = ProcessRetryOptions::from_lua(options.clone(), &lua)?;
+    let output = ProcessOutputCallbacks::from_lua(options.clone(), &lua)?;
+    let options = ProcessSpawnOptions::from_lua(options, &lua)?;
+
+    let mut attempt = 1;
+    loop {
+        let result = exec_once(
+            lua.clone(),
+            program.clone(),
+            args.clone(),
+            options.clone(),
+            output.clone(),
+        )
+        .await?;
+
+        let code = result.get::<i32>("code")?;
+        if attempt >= retry.attempts || !retry.should_retry(code) {
+            return Ok((result, attempt));
+        }
+
+        Timer::after(retry.delay_after(attempt)).await;
+        attempt += 1;
+    }
+}
+
+async fn process_exec_json(
+    lua: Lua,
+    (program, args, options): (String, ProcessArgs, LuaValue),
+) -> LuaResult<LuaValue> {
+    let output = ProcessOutputCallbacks::from_lua(options.clone(), &lua)?;
+    let options = ProcessSpawnOptions::from_lua(options, &lua)?;
+    let result = exec_once(lua.clone(), program.clone(), args, options, output).await?;
+
+    // The raw output is included in errors, since it is usually what explains them
+    let stdout = result.get::<LuaString>("stdout")?;
+    if !result.get::<bool>("ok")? {
+        let code = result.get::<i32>("code")?;
+        let stderr = result.get::<LuaString>("stderr")?;
+        return Err(LuaError::runtime(format!(
+            "Command '{program}' exited with code {code}\nstdout: {}\nstderr: {}",
+            stdout.to_string_lossy(),
+            stderr.to_string_lossy()
+        )));
+    }
+
+    decode(stdout.as_bytes(), &lua, EncodeDecodeFormat::Json.into()).map_err(|err| {
+        LuaError::runtime(format!(
+            "Command '{program}' did not output valid JSON: {err}\nstdout: {}",
+            stdout.to_string_lossy()
+        ))
+    })
+}
+
+fn process_exec_async(
+    lua: &Lua,
+    (program, args, options): (String, ProcessArgs, LuaValue),
+) -> LuaResult<exec::ExecHandle> {
+    let output = ProcessOutputCallbacks::from_lua(options.clone(), lua)?;
+    let options = ProcessSpawnOptions::from_lua(options, lua)?;
+    let (child, stdin, stdout, stderr) = spawn_exec_child(program, args, options)?;
+    Ok(exec::ExecHandle::new(
+        lua, child, stdin, stdout, stderr, output,
+    ))
+}
+
+fn spawn_exec_child(
+    program: String,
+    args: ProcessArgs,
+    mut options: ProcessSpawnOptions,
+) -> LuaResult<(
+    Child,
+    Option<Vec<u8>>,
+    ProcessSpawnOptionsStdioKind,
+    ProcessSpawnOptionsStdioKind,
+)> {
     let stdin = options.stdio.stdin.take();
     let stdout = options.stdio.stdout;
     let stderr = options.stdio.stderr;
 
-    let stdin_stdio = if stdin.is_some() {
+    let stdin_stdio = if let Some(file) = options.open_stdin_file()? {
+        file
+    } else if stdin.is_some() {
         Stdio::piped()
+    } else if options.stdio.inherit_stdin {
+        Stdio::inherit()
     } else {
         Stdio::null()
     };
@@ -140,13 +344,30 @@ async fn process_exec(
         .stderr(stderr.as_stdio())
         .spawn()?;
 
-    exec::exec(lua, child, stdin, stdout, stderr).await
+    Ok((child, stdin, stdout, stderr))
+}
+
+async fn process_shell(lua: Lua, (command, options): (String, LuaValue)) -> LuaResult<LuaTable> {
+    let output = ProcessOutputCallbacks::from_lua(options.clone(), &lua)?;
+    let mut options = ProcessSpawnOptions::from_lua(options, &lua)?;
+    if options.shell.is_none() {
+        options.shell = Some(default_shell().ok_or_else(|| {
+            LuaError::runtime("Failed to find a default shell for the current platform")
+        })?);
+    }
+
+    // The command line is passed to the shell as-is, quoting is up to the caller
+    exec_once(lua, command, ProcessArgs::empty(), options, output).await
 }
 
 fn process_create(
     lua: &Lua,
     (program, args, options): (String, ProcessArgs, ProcessSpawnOptions),
 ) -> LuaResult<LuaValue> {
+    if options.detached {
+        return spawn_detached(program, args, options)?.into_lua(lua);
+    }
+
     let child = options
         .into_command(program, args)
         .stdin(Stdio::piped())
@@ -156,3 +377,117 @@ fn process_create(
 
     create::Child::new(lua, child).into_lua(lua)
 }
+
+fn spawn_detached(
+    program: String,
+    args: ProcessArgs,
+    options: ProcessSpawnOptions,
+) -> LuaResult<u32> {
+    #[cfg(windows)]
+    let priority = options.priority;
+
+    let mut cmd = options.into_std_command(program, args);
+    cmd.stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+
+    // Signals sent to our own process group, such as ctrl+c, should not reach the child
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        // Setting creation flags replaces any previous ones, so keep the priority class
+        cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP | priority.creation_flags());
+    }
+
+    let mut child = cmd.spawn()?;
+    let pid = child.id();
+
+    // NOTE: Nothing ever kills the child, we only wait for it so
+    // that it does not linger as a zombie process once it exits
+    std::thread::spawn(move || child.wait());
+
+    Ok(pid)
+}
+
+async fn process_pipe(
+    lua: Lua,
+    (source, sink): (ProcessCommand, ProcessCommand),
+) -> LuaResult<LuaTable> {
+    let mut source_options = source.options;
+    let source_stdin = source_options.stdio.stdin.take();
+    let source_stdin_file = source_options.open_stdin_file()?;
+
+    // NOTE: Nobody reads the stderr of the source process, so piping it
+    // could make it block forever once the pipe fills up - forward it instead
+    let source_stderr = match source_options.stdio.stderr {
+        ProcessSpawnOptionsStdioKind::None => Stdio::null(),
+        _ => Stdio::inherit(),
+    };
+
+    let mut source_child = source_options
+        .into_command(source.program, source.args)
+        .stdin(if let Some(file) = source_stdin_file {
+            file
+        } else if source_stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(source_stderr)
+        .spawn()?;
+
+    // Hand the read end of the source stdout directly to the sink process,
+    // this way the intermediate data never passes through our own memory
+    let source_stdout = source_child
+        .stdout
+        .take()
+        .expect("source stdout was piped")
+        .into_stdio()
+        .await
+        .into_lua_err()?;
+
+    let sink_options = sink.options;
+    let stdout = sink_options.stdio.stdout;
+    let stderr = sink_options.stdio.stderr;
+
+    let sink_child = sink_options
+        .into_command(sink.program, sink.args)
+        .stdin(source_stdout)
+        .stdout(stdout.as_stdio())
+        .stderr(stderr.as_stdio())
+        .spawn()?;
+
+    let source_stdin_writer = source_child.stdin.take();
+
+    let (result, ()) = try_join!(
+        exec::exec(
+            lua,
+            sink_child,
+            None,
+            stdout,
+            stderr,
+            ProcessOutputCallbacks::default(),
+            None
+        ),
+        async move {
+            // The writer is dropped once done, which closes stdin for the source process
+            if let (Some(stdin), Some(mut writer)) = (source_stdin, source_stdin_writer) {
+                writer.write_all(&stdin).await.into_lua_err()?;
+            }
+            Ok::<_, LuaError>(())
+        }
+    )?;
+
+    source_child.status().await.into_lua_err()?;
+
+    Ok(result)
+}
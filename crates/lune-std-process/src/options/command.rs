@@ -0,0 +1,45 @@
+use lune_utils::process::ProcessArgs;
+use mlua::prelude::*;
+
+use super::ProcessSpawnOptions;
+
+/**
+    A single command given as a table, in the form `{ program, params?, options? }`.
+*/
+#[derive(Debug, Clone)]
+pub(crate) struct ProcessCommand {
+    pub program: String,
+    pub args: ProcessArgs,
+    pub options: ProcessSpawnOptions,
+}
+
+impl FromLua for ProcessCommand {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let LuaValue::Table(tab) = value else {
+            return Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "ProcessCommand".to_string(),
+                message: Some(format!(
+                    "Invalid command - expected table, got {}",
+                    value.type_name()
+                )),
+            });
+        };
+
+        let program = match tab.get::<LuaValue>(1)? {
+            LuaValue::String(s) => s.to_str()?.to_string(),
+            value => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Invalid type for command program - expected string, got '{}'",
+                    value.type_name()
+                )));
+            }
+        };
+
+        Ok(Self {
+            program,
+            args: ProcessArgs::from_lua(tab.get(2)?, lua)?,
+            options: ProcessSpawnOptions::from_lua(tab.get(3)?, lua)?,
+        })
+    }
+}
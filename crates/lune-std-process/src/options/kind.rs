@@ -10,18 +10,25 @@ pub enum ProcessSpawnOptionsStdioKind {
     Default,
     Forward,
     Inherit,
+    Terminal,
     None,
 }
 
 impl ProcessSpawnOptionsStdioKind {
     pub fn all() -> &'static [Self] {
-        &[Self::Default, Self::Forward, Self::Inherit, Self::None]
+        &[
+            Self::Default,
+            Self::Forward,
+            Self::Inherit,
+            Self::Terminal,
+            Self::None,
+        ]
     }
 
     pub fn as_stdio(self) -> Stdio {
         match self {
             Self::None => Stdio::null(),
-            Self::Forward => Stdio::inherit(),
+            Self::Forward | Self::Terminal => Stdio::inherit(),
             _ => Stdio::piped(),
         }
     }
@@ -33,6 +40,7 @@ impl fmt::Display for ProcessSpawnOptionsStdioKind {
             Self::Default => "default",
             Self::Forward => "forward",
             Self::Inherit => "inherit",
+            Self::Terminal => "terminal",
             Self::None => "none",
         };
         f.write_str(s)
@@ -46,6 +54,7 @@ impl FromStr for ProcessSpawnOptionsStdioKind {
             "default" => Self::Default,
             "forward" => Self::Forward,
             "inherit" => Self::Inherit,
+            "terminal" => Self::Terminal,
             "none" => Self::None,
             _ => {
                 return Err(LuaError::RuntimeError(format!(
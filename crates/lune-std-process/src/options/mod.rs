@@ -2,7 +2,9 @@ use std::{
     collections::HashMap,
     env::{self},
     ffi::OsString,
+    fs::File,
     path::PathBuf,
+    process::Stdio,
 };
 
 use lune_utils::process::ProcessArgs;
@@ -11,10 +13,18 @@ use mlua::prelude::*;
 use async_process::Command;
 use directories::UserDirs;
 
+mod command;
 mod kind;
+mod output;
+mod priority;
+mod retry;
 mod stdio;
 
+pub(super) use command::*;
 pub(super) use kind::*;
+pub(super) use output::*;
+pub(super) use priority::*;
+pub(super) use retry::*;
 pub(super) use stdio::*;
 
 #[derive(Debug, Clone, Default)]
@@ -23,6 +33,9 @@ pub(super) struct ProcessSpawnOptions {
     pub envs: HashMap<String, String>,
     pub shell: Option<String>,
     pub stdio: ProcessSpawnOptionsStdio,
+    pub stdin_file: Option<PathBuf>,
+    pub detached: bool,
+    pub priority: ProcessSpawnOptionsPriority,
 }
 
 impl FromLua for ProcessSpawnOptions {
@@ -104,13 +117,7 @@ impl FromLua for ProcessSpawnOptions {
         match value.get("shell")? {
             LuaValue::Nil => {}
             LuaValue::String(s) => this.shell = Some(s.to_string_lossy().to_string()),
-            LuaValue::Boolean(true) => {
-                this.shell = match env::consts::FAMILY {
-                    "unix" => Some("/bin/sh".to_string()),
-                    "windows" => Some("powershell".to_string()),
-                    _ => None,
-                };
-            }
+            LuaValue::Boolean(true) => this.shell = default_shell(),
             value => {
                 return Err(LuaError::RuntimeError(format!(
                     "Invalid type for option 'shell' - expected 'true' or 'string', got '{}'",
@@ -119,6 +126,25 @@ impl FromLua for ProcessSpawnOptions {
             }
         }
 
+        /*
+            If we got the detached flag, make sure it is a boolean
+        */
+        match value.get("detached")? {
+            LuaValue::Nil => {}
+            LuaValue::Boolean(b) => this.detached = b,
+            value => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Invalid type for option 'detached' - expected boolean, got '{}'",
+                    value.type_name()
+                )));
+            }
+        }
+
+        /*
+            If we got a priority, make sure it is one of the known levels
+        */
+        this.priority = value.get("priority")?;
+
         /*
             If we got options for stdio handling, parse those as well
 
@@ -126,12 +152,70 @@ impl FromLua for ProcessSpawnOptions {
         */
         this.stdio = value.get("stdio")?;
 
+        /*
+            If we got a file to use as stdin, make sure it does
+            not conflict with stdin data given in the stdio options
+        */
+        match value.get("stdinFile")? {
+            LuaValue::Nil => {}
+            LuaValue::String(s) => {
+                if this.stdio.stdin.is_some() {
+                    return Err(LuaError::runtime(
+                        "Invalid value for option 'stdinFile' - can not be combined with 'stdio.stdin'",
+                    ));
+                }
+                this.stdin_file = Some(PathBuf::from(s.to_str()?.to_string()));
+            }
+            value => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Invalid type for option 'stdinFile' - expected string, got '{}'",
+                    value.type_name()
+                )));
+            }
+        }
+
         Ok(this)
     }
 }
 
+/**
+    Gets the default shell for the current platform, if any.
+*/
+pub(crate) fn default_shell() -> Option<String> {
+    match env::consts::FAMILY {
+        "unix" => Some("/bin/sh".to_string()),
+        "windows" => Some("powershell".to_string()),
+        _ => None,
+    }
+}
+
 impl ProcessSpawnOptions {
+    /**
+        Opens the file given using the `stdinFile` option, if any, so
+        that it can be handed directly to the child process as its stdin.
+    */
+    pub fn open_stdin_file(&self) -> LuaResult<Option<Stdio>> {
+        let Some(path) = &self.stdin_file else {
+            return Ok(None);
+        };
+        let file = File::open(path).map_err(|e| {
+            LuaError::runtime(format!(
+                "Failed to open stdin file '{}' - {e}",
+                path.display()
+            ))
+        })?;
+        Ok(Some(Stdio::from(file)))
+    }
+
     pub fn into_command(self, program: impl Into<OsString>, args: ProcessArgs) -> Command {
+        Command::from(self.into_std_command(program, args))
+    }
+
+    pub fn into_std_command(
+        self,
+        program: impl Into<OsString>,
+        args: ProcessArgs,
+    ) -> std::process::Command {
         let mut program: OsString = program.into();
         let mut args = args.into_iter().collect::<Vec<_>>();
 
@@ -147,7 +231,7 @@ impl ProcessSpawnOptions {
         }
 
         // Create command with the wanted options
-        let mut cmd = Command::new(program);
+        let mut cmd = std::process::Command::new(program);
         cmd.args(args);
 
         // Set dir to run in and env variables
@@ -158,6 +242,8 @@ impl ProcessSpawnOptions {
             cmd.envs(self.envs);
         }
 
+        self.priority.apply(&mut cmd);
+
         cmd
     }
 }
@@ -0,0 +1,52 @@
+use mlua::prelude::*;
+
+/**
+    Callbacks receiving the output of `process.exec` as it arrives,
+    read from the same table as the spawn options.
+*/
+#[derive(Debug, Clone)]
+pub(crate) struct ProcessOutputCallbacks {
+    pub on_stdout: Option<LuaFunction>,
+    pub on_stderr: Option<LuaFunction>,
+    // Whether output is also kept for the result, or only given to the callbacks
+    pub retain: bool,
+}
+
+impl Default for ProcessOutputCallbacks {
+    fn default() -> Self {
+        Self {
+            on_stdout: None,
+            on_stderr: None,
+            retain: true,
+        }
+    }
+}
+
+impl FromLua for ProcessOutputCallbacks {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let mut this = Self::default();
+        let value = match value {
+            LuaValue::Nil => return Ok(this),
+            LuaValue::Table(t) => t,
+            _ => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "ProcessOutputCallbacks".to_string(),
+                    message: Some(format!(
+                        "Invalid output options - expected table, got {}",
+                        value.type_name()
+                    )),
+                });
+            }
+        };
+
+        this.on_stdout = value.get("onStdout")?;
+        this.on_stderr = value.get("onStderr")?;
+
+        if let Some(retain) = value.get::<Option<bool>>("retainOutput")? {
+            this.retain = retain;
+        }
+
+        Ok(this)
+    }
+}
@@ -0,0 +1,129 @@
+use std::{fmt, str::FromStr};
+
+use mlua::prelude::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProcessSpawnOptionsPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl ProcessSpawnOptionsPriority {
+    pub fn all() -> &'static [Self] {
+        &[Self::Low, Self::Normal, Self::High]
+    }
+
+    /**
+        Gets how much to change the nice value of the child process by,
+        relative to the nice value of this process, which it inherits.
+
+        Raising the priority above normal requires elevated
+        privileges, and will make spawning fail without them.
+    */
+    #[cfg(unix)]
+    pub fn nice_offset(self) -> Option<i32> {
+        match self {
+            Self::Low => Some(10),
+            Self::Normal => None,
+            Self::High => Some(-10),
+        }
+    }
+
+    /**
+        Gets the priority class creation flag to spawn the child process with.
+    */
+    #[cfg(windows)]
+    pub fn creation_flags(self) -> u32 {
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+        const HIGH_PRIORITY_CLASS: u32 = 0x0000_0080;
+        match self {
+            Self::Low => BELOW_NORMAL_PRIORITY_CLASS,
+            Self::Normal => 0,
+            Self::High => HIGH_PRIORITY_CLASS,
+        }
+    }
+
+    /**
+        Applies the priority to the given command, if it is not the default.
+    */
+    pub fn apply(self, cmd: &mut std::process::Command) {
+        #[cfg(unix)]
+        if let Some(offset) = self.nice_offset() {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: getpriority and setpriority are async-signal-safe and do not allocate
+            unsafe {
+                cmd.pre_exec(move || {
+                    // Getting the priority of the calling process can not fail, so -1 is
+                    // always a real nice value here, and never needs to be checked for errors
+                    let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+                    let nice = (current + offset).clamp(-20, 19);
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(self.creation_flags());
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        let _ = cmd;
+    }
+}
+
+impl fmt::Display for ProcessSpawnOptionsPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+        };
+        f.write_str(s)
+    }
+}
+
+impl FromStr for ProcessSpawnOptionsPriority {
+    type Err = LuaError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "low" => Self::Low,
+            "normal" => Self::Normal,
+            "high" => Self::High,
+            _ => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Invalid spawn options priority - got '{}', expected one of {}",
+                    s,
+                    ProcessSpawnOptionsPriority::all()
+                        .iter()
+                        .map(|k| format!("'{k}'"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )));
+            }
+        })
+    }
+}
+
+impl FromLua for ProcessSpawnOptionsPriority {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::String(s) => s.to_str()?.parse(),
+            _ => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "ProcessSpawnOptionsPriority".to_string(),
+                message: Some(format!(
+                    "Invalid spawn options priority - expected string, got {}",
+                    value.type_name()
+                )),
+            }),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use mlua::prelude::*;
+
+const DEFAULT_ATTEMPTS: u32 = 3;
+const DEFAULT_BASE_DELAY_MS: u64 = 100;
+
+/**
+    Options for `process.execRetry`, read from the same table as the spawn options.
+*/
+#[derive(Debug, Clone)]
+pub(crate) struct ProcessRetryOptions {
+    pub attempts: u32,
+    pub base_delay: Duration,
+    // Exit codes to retry on, or any nonzero exit code when empty
+    pub retry_on: Vec<i32>,
+}
+
+impl Default for ProcessRetryOptions {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_ATTEMPTS,
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+            retry_on: Vec::new(),
+        }
+    }
+}
+
+impl ProcessRetryOptions {
+    pub fn should_retry(&self, code: i32) -> bool {
+        if self.retry_on.is_empty() {
+            code != 0
+        } else {
+            self.retry_on.contains(&code)
+        }
+    }
+
+    /**
+        Gets the delay to wait after the given failed attempt, starting at 1,
+        doubling the base delay for every attempt after the first one.
+    */
+    pub fn delay_after(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        self.base_delay.saturating_mul(factor)
+    }
+}
+
+impl FromLua for ProcessRetryOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let mut this = Self::default();
+        let value = match value {
+            LuaValue::Nil => return Ok(this),
+            LuaValue::Table(t) => t,
+            _ => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "ProcessRetryOptions".to_string(),
+                    message: Some(format!(
+                        "Invalid retry options - expected table, got {}",
+                        value.type_name()
+                    )),
+                });
+            }
+        };
+
+        if let Some(attempts) = value.get::<Option<u32>>("attempts")? {
+            if attempts == 0 {
+                return Err(LuaError::runtime(
+                    "Invalid value for option 'attempts' - must be at least 1",
+                ));
+            }
+            this.attempts = attempts;
+        }
+
+        if let Some(base_delay) = value.get::<Option<u64>>("baseDelayMs")? {
+            this.base_delay = Duration::from_millis(base_delay);
+        }
+
+        if let Some(retry_on) = value.get::<Option<Vec<i32>>>("retryOn")? {
+            this.retry_on = retry_on;
+        }
+
+        Ok(this)
+    }
+}
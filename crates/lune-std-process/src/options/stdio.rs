@@ -8,6 +8,8 @@ pub struct ProcessSpawnOptionsStdio {
     pub stdout: ProcessSpawnOptionsStdioKind,
     pub stderr: ProcessSpawnOptionsStdioKind,
     pub stdin: Option<Vec<u8>>,
+    // Only set using the string form, since stdin in the table form is data to write
+    pub inherit_stdin: bool,
 }
 
 impl From<ProcessSpawnOptionsStdioKind> for ProcessSpawnOptionsStdio {
@@ -15,6 +17,7 @@ impl From<ProcessSpawnOptionsStdioKind> for ProcessSpawnOptionsStdio {
         Self {
             stdout: value,
             stderr: value,
+            inherit_stdin: value == ProcessSpawnOptionsStdioKind::Terminal,
             ..Default::default()
         }
     }
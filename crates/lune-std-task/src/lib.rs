@@ -3,12 +3,23 @@
 #![allow(clippy::needless_borrow)]
 #![allow(clippy::pedantic)]
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::future::poll_fn;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
 use std::thread;
 use std::time::{Duration, Instant};
 
-use async_channel::{Receiver, Sender};
+use async_channel::{Receiver, Sender, unbounded};
 use async_io::Timer;
-use futures_lite::future::yield_now;
+use futures_lite::{
+    FutureExt,
+    future::{block_on, or, yield_now},
+};
 
 use mlua::prelude::*;
 use mlua_luau_scheduler::Functions;
@@ -26,6 +37,7 @@ pub fn typedefs() -> String {
 enum ThreadValue {
     Nil,
     Bool(bool),
+    Integer(i64),
     Number(f64),
     String(String),
     Table(Vec<(ThreadValue, ThreadValue)>),
@@ -35,14 +47,24 @@ fn to_thread_value(lua: &Lua, value: LuaValue) -> LuaResult<ThreadValue> {
     match value {
         LuaValue::Nil => Ok(ThreadValue::Nil),
         LuaValue::Boolean(b) => Ok(ThreadValue::Bool(b)),
-        LuaValue::Integer(i) => Ok(ThreadValue::Number(i as f64)),
+        LuaValue::Integer(i) => Ok(ThreadValue::Integer(i)),
         LuaValue::Number(n) => Ok(ThreadValue::Number(n)),
         LuaValue::String(s) => Ok(ThreadValue::String(s.to_str()?.to_string())),
         LuaValue::Table(t) => {
             let mut entries = Vec::new();
             for pair in t.pairs::<LuaValue, LuaValue>() {
                 let (k, v) = pair?;
-                entries.push((to_thread_value(lua, k)?, to_thread_value(lua, v)?));
+                let key = to_thread_value(lua, k)?;
+                match key {
+                    ThreadValue::Nil => {
+                        return Err(LuaError::external("table key can not be nil for threading"));
+                    }
+                    ThreadValue::Number(n) if n.is_nan() => {
+                        return Err(LuaError::external("table key can not be NaN for threading"));
+                    }
+                    _ => {}
+                }
+                entries.push((key, to_thread_value(lua, v)?));
             }
             Ok(ThreadValue::Table(entries))
         }
@@ -54,6 +76,7 @@ fn from_thread_value(lua: &Lua, value: ThreadValue) -> LuaResult<LuaValue> {
     match value {
         ThreadValue::Nil => Ok(LuaValue::Nil),
         ThreadValue::Bool(b) => Ok(LuaValue::Boolean(b)),
+        ThreadValue::Integer(i) => Ok(LuaValue::Integer(i)),
         ThreadValue::Number(n) => Ok(LuaValue::Number(n)),
         ThreadValue::String(s) => Ok(LuaValue::String(lua.create_string(&s)?)),
         ThreadValue::Table(entries) => {
@@ -66,9 +89,114 @@ fn from_thread_value(lua: &Lua, value: ThreadValue) -> LuaResult<LuaValue> {
     }
 }
 
+// Message sent from a parallel worker back to the main thread, tagged
+// with its kind so that progress can be told apart from final results
+#[derive(Clone, Debug)]
+enum WorkerMessage {
+    Result(Vec<ThreadValue>),
+    Progress(Vec<ThreadValue>),
+    Done(Vec<ThreadValue>),
+    // Sent by the worker thread if its script errors, right before the channel closes
+    Error(String),
+}
+
+impl WorkerMessage {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Result(_) => "result",
+            Self::Progress(_) => "progress",
+            Self::Done(_) => "done",
+            Self::Error(_) => "error",
+        }
+    }
+}
+
 struct ParallelTask {
     tx: Sender<Vec<ThreadValue>>,
-    rx: Receiver<Vec<ThreadValue>>,
+    rx: Receiver<WorkerMessage>,
+    // async_channel has no way to peek, so a peeked message is held here until popped
+    peeked: RefCell<Option<WorkerMessage>>,
+    name: Option<String>,
+    // Set by the worker thread if its script errors, before the channel closes
+    error: Arc<Mutex<Option<String>>>,
+    // Lines printed by the worker, queued until read using ReadLog
+    logs: Receiver<String>,
+}
+
+impl ParallelTask {
+    fn closed_error(&self) -> LuaError {
+        let error = self.error.lock().unwrap().clone();
+        match error {
+            Some(err) => self.worker_error(&err),
+            None => LuaError::external("channel closed"),
+        }
+    }
+
+    fn worker_error(&self, err: &str) -> LuaError {
+        match &self.name {
+            Some(name) => LuaError::runtime(format!("worker '{name}' errored: {err}")),
+            None => LuaError::runtime(format!("worker errored: {err}")),
+        }
+    }
+
+    // Values of any kind of message, the same way they were popped before messages were tagged
+    fn message_values(&self, message: WorkerMessage) -> LuaResult<Vec<ThreadValue>> {
+        match message {
+            WorkerMessage::Result(values)
+            | WorkerMessage::Progress(values)
+            | WorkerMessage::Done(values) => Ok(values),
+            WorkerMessage::Error(err) => Err(self.worker_error(&err)),
+        }
+    }
+}
+
+// A native function given to worker scripts as a global, lets tests panic inside real workers
+#[cfg(test)]
+type WorkerNative = fn(&Lua, LuaMultiValue) -> LuaResult<LuaMultiValue>;
+
+#[derive(Clone, Default)]
+struct ParallelOptions {
+    name: Option<String>,
+    env: HashMap<String, String>,
+    #[cfg(test)]
+    natives: Vec<(&'static str, WorkerNative)>,
+}
+
+impl FromLua for ParallelOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Table(t) => Ok(Self {
+                name: t.get("name")?,
+                env: t
+                    .get::<Option<HashMap<String, String>>>("env")?
+                    .unwrap_or_default(),
+                #[cfg(test)]
+                natives: Vec::new(),
+            }),
+            _ => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "ParallelOptions".to_string(),
+                message: Some("expected a table of options or nil".to_string()),
+            }),
+        }
+    }
+}
+
+fn from_thread_values(lua: &Lua, values: Vec<ThreadValue>) -> LuaResult<LuaMultiValue> {
+    let mut result = Vec::new();
+    for value in values {
+        result.push(from_thread_value(lua, value)?);
+    }
+
+    Ok(LuaMultiValue::from_vec(result))
+}
+
+// Same shape as table.pack, so that nil values are not lost
+fn pack_thread_values(lua: &Lua, values: Vec<ThreadValue>) -> LuaResult<LuaTable> {
+    let packed = lua.create_sequence_from(from_thread_values(lua, values)?)?;
+    packed.raw_set("n", packed.raw_len())?;
+    Ok(packed)
 }
 
 impl LuaUserData for ParallelTask {
@@ -86,17 +214,82 @@ impl LuaUserData for ParallelTask {
         });
 
         methods.add_method("Pop", |lua, this, ()| {
-            let values = this
-                .rx
-                .recv_blocking()
-                .map_err(|_| LuaError::external("channel closed"))?;
+            let message = match this.peeked.borrow_mut().take() {
+                Some(message) => message,
+                None => this.rx.recv_blocking().map_err(|_| this.closed_error())?,
+            };
 
-            let mut result = Vec::new();
-            for value in values {
-                result.push(from_thread_value(lua, value)?);
+            from_thread_values(lua, this.message_values(message)?)
+        });
+
+        methods.add_async_method("PopAsync", |lua, this, ()| async move {
+            let peeked = this.peeked.borrow_mut().take();
+            let message = match peeked {
+                Some(message) => message,
+                // Yields to the scheduler while waiting, instead of blocking the thread
+                None => this.rx.recv().await.map_err(|_| this.closed_error())?,
+            };
+
+            from_thread_values(&lua, this.message_values(message)?)
+        });
+
+        methods.add_async_method("Next", |lua, this, ()| async move {
+            let peeked = this.peeked.borrow_mut().take();
+            let message = match peeked {
+                Some(message) => message,
+                None => this.rx.recv().await.map_err(|_| this.closed_error())?,
+            };
+
+            let kind = message.kind();
+            let values = match message {
+                WorkerMessage::Result(values)
+                | WorkerMessage::Progress(values)
+                | WorkerMessage::Done(values) => values,
+                WorkerMessage::Error(err) => vec![ThreadValue::String(err)],
+            };
+
+            Ok((kind, pack_thread_values(&lua, values)?))
+        });
+
+        methods.add_method("Peek", |lua, this, ()| {
+            let mut peeked = this.peeked.borrow_mut();
+
+            if peeked.is_none() {
+                match this.rx.try_recv() {
+                    Ok(message) => *peeked = Some(message),
+                    Err(_) => return Ok(LuaMultiValue::from_vec(vec![LuaValue::Nil])),
+                }
             }
 
-            Ok(LuaMultiValue::from_vec(result))
+            // The error is only thrown once it is popped, same as before it was received
+            match peeked.clone() {
+                Some(WorkerMessage::Error(_)) | None => {
+                    Ok(LuaMultiValue::from_vec(vec![LuaValue::Nil]))
+                }
+                Some(message) => from_thread_values(lua, this.message_values(message)?),
+            }
+        });
+
+        methods.add_method("Drain", |lua, this, ()| {
+            let batches = lua.create_table()?;
+
+            let peeked = this.peeked.borrow_mut().take();
+            let available = std::iter::from_fn(|| this.rx.try_recv().ok());
+
+            for message in peeked.into_iter().chain(available) {
+                // Errors are kept for the next pop to throw, instead of losing the drained batches
+                if let WorkerMessage::Error(_) = message {
+                    *this.peeked.borrow_mut() = Some(message);
+                    break;
+                }
+                batches.raw_push(pack_thread_values(lua, this.message_values(message)?)?)?;
+            }
+
+            Ok(batches)
+        });
+
+        methods.add_method("ReadLog", |lua, this, ()| {
+            lua.create_sequence_from(std::iter::from_fn(|| this.logs.try_recv().ok()))
         });
 
         methods.add_method("Close", |_, this, ()| {
@@ -106,14 +299,165 @@ impl LuaUserData for ParallelTask {
     }
 }
 
+async fn select(lua: Lua, tasks: Vec<LuaUserDataRef<ParallelTask>>) -> LuaResult<LuaMultiValue> {
+    if tasks.is_empty() {
+        return Err(LuaError::runtime(
+            "expected at least one task to select from",
+        ));
+    }
+
+    // A peeked message is already available, so there is nothing to wait for
+    for (index, task) in tasks.iter().enumerate() {
+        let peeked = task.peeked.borrow_mut().take();
+        if let Some(message) = peeked {
+            return select_result(&lua, index, task.message_values(message)?);
+        }
+    }
+
+    // Receiving is cancel-safe, so racing all of the receivers
+    // never loses values from the tasks that did not win the race
+    let mut receivers = tasks
+        .iter()
+        .map(|task| Box::pin(task.rx.recv()))
+        .collect::<Vec<_>>();
+
+    // Workers that stopped without erroring can never produce values again,
+    // so they are skipped, unless every one of the workers has stopped
+    let mut stopped = vec![false; tasks.len()];
+
+    let (index, result) = poll_fn(|cx| {
+        for (index, recv) in receivers.iter_mut().enumerate() {
+            if stopped[index] {
+                continue;
+            }
+            match recv.poll(cx) {
+                Poll::Ready(Err(_)) if tasks[index].error.lock().unwrap().is_none() => {
+                    stopped[index] = true;
+                }
+                Poll::Ready(result) => return Poll::Ready((index, result)),
+                Poll::Pending => {}
+            }
+        }
+        if stopped.iter().all(|stopped| *stopped) {
+            Poll::Ready((0, Err(async_channel::RecvError)))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+
+    let message = result.map_err(|_| tasks[index].closed_error())?;
+    select_result(&lua, index, tasks[index].message_values(message)?)
+}
+
+fn select_result(lua: &Lua, index: usize, values: Vec<ThreadValue>) -> LuaResult<LuaMultiValue> {
+    let mut result = from_thread_values(lua, values)?;
+    result.push_front(LuaValue::Integer((index + 1) as i64));
+    Ok(result)
+}
+
+// Results gathered from several workers, iterated with a generic for or called to get the next one
+struct GatheredResults {
+    tasks: Vec<LuaAnyUserData>,
+}
+
+impl LuaUserData for GatheredResults {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        // Calling yields to the scheduler while waiting instead, the same as PopAsync
+        fields.add_meta_field_with(LuaMetaMethod::Call, |lua| {
+            lua.create_async_function(|lua, this: LuaUserDataRef<Self>| {
+                let tasks = this.tasks.clone();
+                async move { gather_next(lua, tasks).await }
+            })
+        });
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Generic for loops can not yield, so iterating blocks while waiting, the same as Pop
+        methods.add_meta_method(LuaMetaMethod::Iter, |lua, this, (): ()| {
+            let tasks = this.tasks.clone();
+            lua.create_function(move |lua, _: LuaMultiValue| {
+                block_on(gather_next(lua.clone(), tasks.clone()))
+            })
+        });
+    }
+}
+
+fn gather(lua: &Lua, tasks: Vec<LuaAnyUserData>) -> LuaResult<LuaAnyUserData> {
+    for task in &tasks {
+        task.borrow::<ParallelTask>()?;
+    }
+
+    lua.create_userdata(GatheredResults { tasks })
+}
+
+async fn gather_next(lua: Lua, tasks: Vec<LuaAnyUserData>) -> LuaResult<LuaMultiValue> {
+    let tasks = tasks
+        .iter()
+        .map(LuaAnyUserData::borrow::<ParallelTask>)
+        .collect::<LuaResult<Vec<_>>>()?;
+
+    for (index, task) in tasks.iter().enumerate() {
+        let peeked = task.peeked.borrow_mut().take();
+        if let Some(message) = peeked {
+            return gather_result(&lua, index, task.message_values(message)?);
+        }
+    }
+
+    let mut receivers = tasks
+        .iter()
+        .map(|task| Box::pin(task.rx.recv()))
+        .collect::<Vec<_>>();
+
+    // Unlike select, errors have already been delivered as messages by the time
+    // a channel closes, so every closed channel counts as exhausted
+    let mut stopped = vec![false; tasks.len()];
+
+    let next = poll_fn(|cx| {
+        for (index, recv) in receivers.iter_mut().enumerate() {
+            if stopped[index] {
+                continue;
+            }
+            match recv.poll(cx) {
+                Poll::Ready(Ok(message)) => return Poll::Ready(Some((index, message))),
+                Poll::Ready(Err(_)) => stopped[index] = true,
+                Poll::Pending => {}
+            }
+        }
+        if stopped.iter().all(|stopped| *stopped) {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+
+    match next {
+        Some((index, message)) => gather_result(&lua, index, tasks[index].message_values(message)?),
+        None => Ok(LuaMultiValue::from_vec(vec![LuaValue::Nil])),
+    }
+}
+
+fn gather_result(lua: &Lua, index: usize, values: Vec<ThreadValue>) -> LuaResult<LuaMultiValue> {
+    (index + 1, pack_thread_values(lua, values)?).into_lua_multi(lua)
+}
+
 fn install_worker_api(
     lua: &Lua,
-    tx_out: Sender<Vec<ThreadValue>>,
+    tx_out: Sender<WorkerMessage>,
     rx_in: Receiver<Vec<ThreadValue>>,
+    tx_log: Sender<String>,
+    options: ParallelOptions,
 ) -> LuaResult<()> {
     let globals = lua.globals();
     let task = lua.create_table()?;
 
+    if let Some(name) = options.name {
+        globals.set("_WORKER_NAME", name)?;
+    }
+
+    task.set("env", lua.create_table_from(options.env)?)?;
+
     task.set(
         "pop",
         lua.create_function(move |lua, ()| {
@@ -133,46 +477,421 @@ fn install_worker_api(
         })?,
     )?;
 
-    task.set(
-        "push",
+    let sender = |wrap: fn(Vec<ThreadValue>) -> WorkerMessage| {
+        let tx_out = tx_out.clone();
         lua.create_function(move |lua, args: LuaMultiValue| {
             let mut converted = Vec::new();
             for value in args {
                 converted.push(to_thread_value(&lua, value)?);
             }
 
-            if tx_out.send_blocking(converted).is_err() {
+            if tx_out.send_blocking(wrap(converted)).is_err() {
                 return Ok(());
             }
 
+            Ok(())
+        })
+    };
+
+    task.set("push", sender(WorkerMessage::Result)?)?;
+    task.set("progress", sender(WorkerMessage::Progress)?)?;
+    task.set("done", sender(WorkerMessage::Done)?)?;
+
+    globals.set("task", task)?;
+    #[cfg(test)]
+    install_worker_natives(lua, &options.natives)?;
+
+    // Printed lines go back to the main thread instead of being
+    // interleaved with its output, the same way print formats them
+    let tostring = globals.get::<LuaFunction>("tostring")?;
+    globals.set(
+        "print",
+        lua.create_function(move |_, args: LuaMultiValue| {
+            let mut parts = Vec::with_capacity(args.len());
+            for value in args {
+                parts.push(tostring.call::<String>(value)?);
+            }
+            let _ = tx_log.send_blocking(parts.join("\t"));
             Ok(())
         })?,
     )?;
 
-    globals.set("task", task)?;
     Ok(())
 }
 
-fn parallel(lua: &Lua, script: String) -> LuaResult<LuaAnyUserData> {
+/**
+    Finds the file for a module required by a worker script, given by its path
+    relative to the directory of the script, with or without its extension.
+*/
+fn resolve_worker_module(dir: &Path, name: &str) -> LuaResult<PathBuf> {
+    let base = dir.join(name);
+    let with_extension = |ext: &str| {
+        let mut path = base.clone().into_os_string();
+        path.push(ext);
+        PathBuf::from(path)
+    };
+
+    [
+        base.clone(),
+        with_extension(".luau"),
+        with_extension(".lua"),
+        base.join("init.luau"),
+        base.join("init.lua"),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+    .ok_or_else(|| LuaError::runtime(format!("module '{name}' not found in '{}'", dir.display())))
+}
+
+/**
+    Installs a minimal `require` for workers running a script from a file.
+
+    Modules are loaded relative to the directory of that script, and each
+    module only runs once, with later requires returning the same value.
+*/
+fn install_worker_require(lua: &Lua, dir: PathBuf) -> LuaResult<()> {
+    // Modules that are still loading are stored as None, to catch cyclic requires
+    let loaded = RefCell::new(HashMap::<PathBuf, Option<LuaValue>>::new());
+
+    let require = lua.create_function(move |lua, name: String| {
+        let path = resolve_worker_module(&dir, &name)?;
+
+        match loaded.borrow().get(&path) {
+            Some(Some(value)) => return Ok(value.clone()),
+            Some(None) => {
+                return Err(LuaError::runtime(format!(
+                    "cyclic require of module '{name}'"
+                )));
+            }
+            None => {}
+        }
+
+        let source = fs::read_to_string(&path)
+            .map_err(|err| LuaError::runtime(format!("failed to read module '{name}': {err}")))?;
+
+        loaded.borrow_mut().insert(path.clone(), None);
+        let result = lua
+            .load(source)
+            .set_name(format!("@{}", path.display()))
+            .eval::<LuaValue>();
+
+        let value = match result {
+            Ok(LuaValue::Nil) => LuaValue::Boolean(true),
+            Ok(value) => value,
+            Err(err) => {
+                loaded.borrow_mut().remove(&path);
+                return Err(err);
+            }
+        };
+
+        loaded.borrow_mut().insert(path, Some(value.clone()));
+        Ok(value)
+    })?;
+
+    lua.globals().set("require", require)
+}
+
+/**
+    Runs the body of a worker thread, turning a panic into an error
+    message instead of letting it unwind out of the thread.
+*/
+fn catch_worker_panic<T>(body: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(format!("worker panicked: {message}"))
+        }
+    }
+}
+
+fn parallel(
+    lua: &Lua,
+    script: String,
+    path: Option<PathBuf>,
+    options: ParallelOptions,
+) -> LuaResult<LuaAnyUserData> {
     let (tx_in, rx_in) = async_channel::unbounded::<Vec<ThreadValue>>();
-    let (tx_out, rx_out) = async_channel::unbounded::<Vec<ThreadValue>>();
+    let (tx_out, rx_out) = async_channel::unbounded::<WorkerMessage>();
+    let (tx_log, rx_log) = async_channel::unbounded::<String>();
+
+    let name = options.name.clone();
+    let error = Arc::new(Mutex::new(None));
+    let worker_error = Arc::clone(&error);
 
     thread::spawn(move || {
-        let worker_lua = Lua::new();
+        let name = options.name.clone();
+        let tx_error = tx_out.clone();
+
+        // A panic only takes down this worker, and is reported the same way as a script error
+        let result = catch_worker_panic(move || {
+            let worker_lua = Lua::new();
+            install_worker_api(&worker_lua, tx_out, rx_in, tx_log, options)
+                .map_err(|err| err.to_string())?;
 
-        install_worker_api(&worker_lua, tx_out.clone(), rx_in.clone())
-            .expect("failed to install worker api");
+            let mut chunk = worker_lua.load(&script);
+            if let Some(path) = path {
+                let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                install_worker_require(&worker_lua, dir).map_err(|err| err.to_string())?;
+                chunk = chunk.set_name(format!("@{}", path.display()));
+            }
+
+            chunk.exec().map_err(|err| err.to_string())
+        });
 
-        if let Err(err) = worker_lua.load(&script).exec() {
-            eprintln!("Worker script error: {err}");
+        if let Err(err) = result {
+            match &name {
+                Some(name) => eprintln!("Worker '{name}' script error: {err}"),
+                None => eprintln!("Worker script error: {err}"),
+            }
+            *worker_error.lock().unwrap() = Some(err.clone());
+            let _ = tx_error.send_blocking(WorkerMessage::Error(err));
         }
     });
 
     lua.create_userdata(ParallelTask {
         tx: tx_in,
         rx: rx_out,
+        peeked: RefCell::new(None),
+        name,
+        error,
+        logs: rx_log,
+    })
+}
+// Result of a single script evaluated by a persistent worker, or its error message
+type EvalResult = Result<Vec<ThreadValue>, String>;
+
+struct PersistentWorker {
+    jobs: Sender<WorkerJob>,
+    name: Option<String>,
+}
+
+impl PersistentWorker {
+    fn eval_error(&self, err: String) -> LuaError {
+        match &self.name {
+            Some(name) => LuaError::runtime(format!("worker '{name}' errored: {err}")),
+            None => LuaError::runtime(format!("worker errored: {err}")),
+        }
+    }
+}
+
+impl LuaUserData for PersistentWorker {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method("Eval", |lua, this, script: String| async move {
+            let (tx, rx) = async_channel::bounded(1);
+            this.jobs
+                .send((script, tx))
+                .await
+                .map_err(|_| LuaError::external("worker closed"))?;
+
+            // Yields to the scheduler while the script runs
+            let result = rx
+                .recv()
+                .await
+                .map_err(|_| LuaError::external("worker closed"))?;
+
+            match result {
+                Ok(values) => from_thread_values(&lua, values),
+                Err(err) => Err(this.eval_error(err)),
+            }
+        });
+
+        methods.add_method("Close", |_, this, ()| {
+            this.jobs.close();
+            Ok(())
+        });
+    }
+}
+
+// The globals given to scripts run by a persistent worker or thread pool
+fn install_eval_api(lua: &Lua, options: ParallelOptions) -> LuaResult<()> {
+    let globals = lua.globals();
+    if let Some(name) = options.name {
+        globals.set("_WORKER_NAME", name)?;
+    }
+
+    let task = lua.create_table()?;
+    task.set("env", lua.create_table_from(options.env)?)?;
+    globals.set("task", task)?;
+
+    #[cfg(test)]
+    install_worker_natives(lua, &options.natives)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn install_worker_natives(lua: &Lua, natives: &[(&'static str, WorkerNative)]) -> LuaResult<()> {
+    let globals = lua.globals();
+    for &(name, native) in natives {
+        globals.set(name, lua.create_function(native)?)?;
+    }
+    Ok(())
+}
+
+fn eval_script(lua: &Lua, chunk: LuaChunk) -> EvalResult {
+    chunk
+        .eval::<LuaMultiValue>()
+        .and_then(|values| {
+            values
+                .into_iter()
+                .map(|value| to_thread_value(lua, value))
+                .collect::<LuaResult<Vec<_>>>()
+        })
+        .map_err(|err| err.to_string())
+}
+
+fn worker(lua: &Lua, options: ParallelOptions) -> LuaResult<LuaAnyUserData> {
+    let (tx_jobs, rx_jobs) = async_channel::unbounded::<WorkerJob>();
+
+    let name = options.name.clone();
+
+    thread::spawn(move || {
+        let worker_lua = Lua::new();
+        // Failing to start up is reported by every script, instead of only closing the worker
+        let setup = catch_worker_panic(|| {
+            install_eval_api(&worker_lua, options).map_err(|err| err.to_string())
+        });
+
+        // Globals live in the same Lua for as long as the worker, so state persists between scripts
+        while let Ok((script, tx_result)) = rx_jobs.recv_blocking() {
+            // A panic only fails the script that caused it, the worker keeps running after it
+            let result = setup.clone().and_then(|()| {
+                catch_worker_panic(|| eval_script(&worker_lua, worker_lua.load(&script)))
+            });
+
+            let _ = tx_result.send_blocking(result);
+        }
+    });
+
+    lua.create_userdata(PersistentWorker {
+        jobs: tx_jobs,
+        name,
     })
 }
+
+// A script submitted to a worker or thread pool, along with where to send its result
+type WorkerJob = (String, Sender<EvalResult>);
+
+struct ThreadPool {
+    jobs: Sender<WorkerJob>,
+    name: Option<String>,
+}
+
+impl LuaUserData for ThreadPool {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("Submit", |_, this, script: String| {
+            let (tx, rx) = async_channel::bounded(1);
+            this.jobs
+                .send_blocking((script, tx))
+                .map_err(|_| LuaError::external("thread pool closed"))?;
+
+            Ok(PoolTask {
+                result: rx,
+                done: RefCell::new(None),
+                name: this.name.clone(),
+            })
+        });
+
+        methods.add_method("Close", |_, this, ()| {
+            this.jobs.close();
+            Ok(())
+        });
+    }
+}
+
+struct PoolTask {
+    result: Receiver<EvalResult>,
+    // The result is kept once received, so awaiting again returns it again
+    done: RefCell<Option<EvalResult>>,
+    name: Option<String>,
+}
+
+impl LuaUserData for PoolTask {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method("Await", |lua, this, ()| async move {
+            let done = this.done.borrow().clone();
+            let result = match done {
+                Some(result) => result,
+                None => {
+                    // Yields to the scheduler while the job is queued or running
+                    let result = this
+                        .result
+                        .recv()
+                        .await
+                        .map_err(|_| LuaError::external("thread pool closed"))?;
+                    *this.done.borrow_mut() = Some(result.clone());
+                    result
+                }
+            };
+
+            match result {
+                Ok(values) => from_thread_values(&lua, values),
+                Err(err) => Err(match &this.name {
+                    Some(name) => LuaError::runtime(format!("pool '{name}' job errored: {err}")),
+                    None => LuaError::runtime(format!("pool job errored: {err}")),
+                }),
+            }
+        });
+
+        methods.add_method("IsDone", |_, this, ()| {
+            Ok(this.done.borrow().is_some() || !this.result.is_empty())
+        });
+    }
+}
+
+fn thread_pool(lua: &Lua, size: usize, options: ParallelOptions) -> LuaResult<LuaAnyUserData> {
+    if size == 0 {
+        return Err(LuaError::runtime("thread pool size must be at least 1"));
+    }
+
+    // Every thread takes jobs from the same queue, so queued jobs go to whichever is free first
+    let (tx_jobs, rx_jobs) = async_channel::unbounded::<WorkerJob>();
+
+    for _ in 0..size {
+        let rx_jobs = rx_jobs.clone();
+        let options = options.clone();
+
+        thread::spawn(move || {
+            let worker_lua = Lua::new();
+            // Failing to start up is reported by every job this thread takes, like in a worker
+            let setup = catch_worker_panic(|| {
+                install_eval_api(&worker_lua, options).map_err(|err| err.to_string())
+            });
+
+            while let Ok((script, tx_result)) = rx_jobs.recv_blocking() {
+                // A panic only fails the job that caused it, the thread keeps taking jobs after it
+                let result = setup.clone().and_then(|()| {
+                    catch_worker_panic(|| {
+                        // Each job gets its own globals, so jobs can not see what ran on the same thread before
+                        let env = worker_lua
+                            .create_table()
+                            .and_then(|env| {
+                                let meta = worker_lua.create_table()?;
+                                meta.set("__index", worker_lua.globals())?;
+                                env.set_metatable(Some(meta))?;
+                                Ok(env)
+                            })
+                            .map_err(|err| err.to_string())?;
+                        eval_script(&worker_lua, worker_lua.load(&script).set_environment(env))
+                    })
+                });
+
+                let _ = tx_result.send_blocking(result);
+            }
+        });
+    }
+
+    lua.create_userdata(ThreadPool {
+        jobs: tx_jobs,
+        name: options.name,
+    })
+}
+
 pub fn module(lua: Lua) -> LuaResult<LuaTable> {
     let fns = Functions::new(lua.clone())?;
 
@@ -191,7 +910,31 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         .set_environment(task_delay_env)
         .into_function()?;
 
-    let task_parallel = lua.create_function(|lua, script: String| parallel(&lua, script))?;
+    let task_parallel =
+        lua.create_function(|lua, (script, options): (String, ParallelOptions)| {
+            parallel(&lua, script, None, options)
+        })?;
+
+    let task_parallel_file =
+        lua.create_function(|lua, (path, options): (String, ParallelOptions)| {
+            let script = fs::read_to_string(&path).map_err(|err| {
+                LuaError::runtime(format!("failed to read worker script '{path}': {err}"))
+            })?;
+            parallel(&lua, script, Some(PathBuf::from(path)), options)
+        })?;
+
+    let task_worker = lua.create_function(|lua, options: ParallelOptions| worker(&lua, options))?;
+
+    let task_thread_pool =
+        lua.create_function(|lua, (size, options): (usize, ParallelOptions)| {
+            thread_pool(&lua, size, options)
+        })?;
+
+    let task_select = lua.create_async_function(select)?;
+    let task_gather = lua.create_function(|lua, tasks: Vec<LuaAnyUserData>| gather(&lua, tasks))?;
+
+    let task_token = lua.create_function(|_, ()| Ok(CancelToken::new()))?;
+    let task_wait_cancellable = lua.create_async_function(wait_cancellable)?;
 
     TableBuilder::new(lua)?
         .with_value("cancel", fns.cancel)?
@@ -200,6 +943,13 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         .with_value("spawn", fns.spawn)?
         .with_value("wait", task_wait)?
         .with_value("parallel", task_parallel)?
+        .with_value("parallelFile", task_parallel_file)?
+        .with_value("worker", task_worker)?
+        .with_value("threadPool", task_thread_pool)?
+        .with_value("select", task_select)?
+        .with_value("gather", task_gather)?
+        .with_value("token", task_token)?
+        .with_value("waitCancellable", task_wait_cancellable)?
         .build_readonly()
 }
 
@@ -215,6 +965,53 @@ async fn wait(lua: Lua, secs: Option<f64>) -> LuaResult<f64> {
     wait_inner(lua, secs).await
 }
 
+#[derive(Clone)]
+struct CancelToken {
+    // Nothing is ever sent, cancelling closes the channel to wake up all waiters at once
+    tx: Sender<()>,
+    rx: Receiver<()>,
+}
+
+impl CancelToken {
+    fn new() -> Self {
+        let (tx, rx) = unbounded();
+        Self { tx, rx }
+    }
+}
+
+impl LuaUserData for CancelToken {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("cancel", |_, this, ()| {
+            this.tx.close();
+            Ok(())
+        });
+
+        methods.add_method("isCancelled", |_, this, ()| Ok(this.tx.is_closed()));
+    }
+}
+
+async fn wait_cancellable(
+    lua: Lua,
+    (secs, token): (Option<f64>, LuaUserDataRef<CancelToken>),
+) -> LuaResult<(f64, bool)> {
+    let token = token.clone();
+    let before = Instant::now();
+
+    let cancelled = or(
+        async {
+            wait(lua, secs).await?;
+            Ok::<_, LuaError>(false)
+        },
+        async {
+            let _ = token.rx.recv().await;
+            Ok(true)
+        },
+    )
+    .await?;
+
+    Ok((before.elapsed().as_secs_f64(), cancelled))
+}
+
 async fn wait_inner(_: Lua, secs: Option<f64>) -> LuaResult<f64> {
     let duration = Duration::from_secs_f64(secs.unwrap_or_default());
     let duration = duration.max(Duration::from_millis(1));
@@ -226,3 +1023,105 @@ async fn wait_inner(_: Lua, secs: Option<f64>) -> LuaResult<f64> {
 
     Ok((after - before).as_secs_f64())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catch_worker_panic_passes_results_through() {
+        assert_eq!(catch_worker_panic(|| Ok(())), Ok(()));
+        assert_eq!(
+            catch_worker_panic::<()>(|| Err("script error".to_string())),
+            Err("script error".to_string())
+        );
+    }
+
+    #[test]
+    fn catch_worker_panic_reports_panics_as_errors() {
+        assert_eq!(
+            catch_worker_panic::<()>(|| panic!("static message")),
+            Err("worker panicked: static message".to_string())
+        );
+
+        let code = 42;
+        assert_eq!(
+            catch_worker_panic::<()>(|| panic!("formatted message {code}")),
+            Err("worker panicked: formatted message 42".to_string())
+        );
+    }
+
+    // Panics inside a real worker, the same way a bug in a native function would
+    fn panic_in_worker(_: &Lua, args: LuaMultiValue) -> LuaResult<LuaMultiValue> {
+        let message = args
+            .into_iter()
+            .next()
+            .and_then(|value| value.as_string().map(|s| s.to_string_lossy()))
+            .unwrap_or_default();
+        panic!("{message}")
+    }
+
+    fn panicking_options() -> ParallelOptions {
+        ParallelOptions {
+            natives: vec![("panicInWorker", panic_in_worker)],
+            ..ParallelOptions::default()
+        }
+    }
+
+    #[test]
+    fn worker_eval_panic_only_fails_that_script() {
+        let lua = Lua::new();
+        let worker = worker(&lua, panicking_options()).unwrap();
+
+        let result = block_on(worker.call_async_method::<()>("Eval", "panicInWorker('boom')"));
+        match result {
+            Err(err) => assert!(
+                err.to_string().contains("worker panicked: boom"),
+                "got {err}"
+            ),
+            Ok(()) => panic!("expected eval to raise the panic"),
+        }
+
+        let value = block_on(worker.call_async_method::<i64>("Eval", "return 1 + 1")).unwrap();
+        assert_eq!(value, 2);
+    }
+
+    #[test]
+    fn pool_job_panic_only_fails_that_job() {
+        let lua = Lua::new();
+        let pool = thread_pool(&lua, 1, panicking_options()).unwrap();
+
+        let job = pool
+            .call_method::<LuaAnyUserData>("Submit", "panicInWorker('boom')")
+            .unwrap();
+        match block_on(job.call_async_method::<()>("Await", ())) {
+            Err(err) => assert!(
+                err.to_string().contains("worker panicked: boom"),
+                "got {err}"
+            ),
+            Ok(()) => panic!("expected await to raise the panic"),
+        }
+
+        // The only thread in the pool is still there to take the next job
+        let job = pool
+            .call_method::<LuaAnyUserData>("Submit", "return 1 + 1")
+            .unwrap();
+        let value = block_on(job.call_async_method::<i64>("Await", ())).unwrap();
+        assert_eq!(value, 2);
+    }
+
+    #[test]
+    fn worker_panic_is_raised_by_pop() {
+        let lua = Lua::new();
+        let script = "panicInWorker('boom')".to_string();
+        let task = parallel(&lua, script, None, panicking_options()).unwrap();
+
+        match task.call_method::<()>("Pop", ()) {
+            Err(err) => assert!(
+                err.to_string().contains("worker panicked: boom"),
+                "got {err}"
+            ),
+            Ok(()) => panic!("expected pop to raise the panic"),
+        }
+    }
+}
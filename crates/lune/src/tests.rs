@@ -97,6 +97,29 @@ create_tests! {
     datetime_to_universal_time: "datetime/toUniversalTime",
 }
 
+#[cfg(feature = "std-file")]
+create_tests! {
+    file_cstring: "file/cstring",
+    file_datetime: "file/datetime",
+    file_diff: "file/diff",
+    file_endianness: "file/endianness",
+    file_fixed: "file/fixed",
+    file_hash: "file/hash",
+    file_lock: "file/lock",
+    file_max_size: "file/max_size",
+    file_merge_safe: "file/merge_safe",
+    file_pack: "file/pack",
+    file_read_string_into: "file/read_string_into",
+    file_read_sized: "file/read_sized",
+    file_reader: "file/reader",
+    file_records: "file/records",
+    file_reserve: "file/reserve",
+    file_safe_cas: "file/safe_cas",
+    file_struct: "file/struct",
+    file_values: "file/values",
+    file_view: "file/view",
+}
+
 #[cfg(feature = "std-fs")]
 create_tests! {
     fs_files: "fs/files",
@@ -114,6 +137,44 @@ create_tests! {
     luau_safeenv: "luau/safeenv",
 }
 
+#[cfg(feature = "std-memory")]
+create_tests! {
+    memory_blob: "memory/blob",
+    memory_collect: "memory/collect",
+    memory_compact: "memory/compact",
+    memory_depth: "memory/depth",
+    memory_find: "memory/find",
+    memory_global_limit: "memory/global_limit",
+    memory_intern: "memory/intern",
+    memory_merge: "memory/merge",
+    memory_ring: "memory/ring",
+    memory_size: "memory/size",
+    memory_slice: "memory/slice",
+    memory_snapshot: "memory/snapshot",
+    memory_typed: "memory/typed",
+    memory_weak: "memory/weak",
+}
+
+#[cfg(feature = "std-mongo")]
+create_tests! {
+    mongo_aggregate: "mongo/aggregate",
+    mongo_bson_types: "mongo/bson_types",
+    mongo_bulk_write: "mongo/bulk_write",
+    mongo_count_documents: "mongo/count_documents",
+    mongo_databases: "mongo/databases",
+    mongo_exists: "mongo/exists",
+    mongo_find_exactly_one: "mongo/find_exactly_one",
+    mongo_find_options: "mongo/find_options",
+    mongo_gridfs: "mongo/gridfs",
+    mongo_insert: "mongo/insert",
+    mongo_ping: "mongo/ping",
+    mongo_projection: "mongo/projection",
+    mongo_rename: "mongo/rename",
+    mongo_replace_one: "mongo/replace_one",
+    mongo_transaction: "mongo/transaction",
+    mongo_typed_numbers: "mongo/typed_numbers",
+}
+
 #[cfg(feature = "std-net")]
 create_tests! {
     net_request_codes: "net/request/codes",
@@ -124,6 +185,7 @@ create_tests! {
     net_request_redirect: "net/request/redirect",
 
     net_serve_addresses: "net/serve/addresses",
+    net_serve_chunked: "net/serve/chunked",
     net_serve_handles: "net/serve/handles",
     net_serve_non_blocking: "net/serve/non_blocking",
     net_serve_requests: "net/serve/requests",
@@ -133,12 +195,32 @@ create_tests! {
     net_socket_wss: "net/socket/wss",
     net_socket_wss_rw: "net/socket/wss_rw",
 
+    net_tcp_accept_websocket: "net/tcp/accept_websocket",
     net_tcp_basic: "net/tcp/basic",
+    net_tcp_connect_retry: "net/tcp/connect_retry",
+    net_tcp_ephemeral: "net/tcp/ephemeral",
+    net_tcp_file: "net/tcp/file",
+    net_tcp_flush: "net/tcp/flush",
     net_tcp_info: "net/tcp/info",
+    net_tcp_ipv6: "net/tcp/ipv6",
+    net_tcp_is_connected: "net/tcp/is_connected",
+    net_tcp_max_connections: "net/tcp/max_connections",
+    net_tcp_peek: "net/tcp/peek",
+    net_tcp_pool: "net/tcp/pool",
+    net_tcp_raw_fd: "net/tcp/raw_fd",
+    net_tcp_read_buffer_size: "net/tcp/read_buffer_size",
+    net_tcp_serve: "net/tcp/serve",
+    net_tcp_timeout: "net/tcp/timeout",
     net_tcp_tls: "net/tcp/tls",
+    net_tcp_writev: "net/tcp/writev",
+
+    net_udp_recv: "net/udp/recv",
+    net_udp_send: "net/udp/send",
+    net_udp_try_recv: "net/udp/try_recv",
 
     net_url_encode: "net/url/encode",
     net_url_decode: "net/url/decode",
+    net_url_parse: "net/url/parse",
 }
 
 #[cfg(feature = "std-process")]
@@ -149,14 +231,29 @@ create_tests! {
     process_exit: "process/exit",
     process_exec_async: "process/exec/async",
     process_exec_basic: "process/exec/basic",
+    process_exec_bytes: "process/exec/bytes",
+    process_exec_callbacks: "process/exec/callbacks",
+    process_exec_cancel: "process/exec/cancel",
     process_exec_cwd: "process/exec/cwd",
+    process_exec_json: "process/exec/json",
     process_exec_no_panic: "process/exec/no_panic",
+    process_exec_retry: "process/exec/retry",
     process_exec_shell: "process/exec/shell",
     process_exec_stdin: "process/exec/stdin",
+    process_exec_stdin_file: "process/exec/stdin_file",
     process_exec_stdio: "process/exec/stdio",
+    process_exec_terminal: "process/exec/terminal",
+    process_pipe_basic: "process/pipe/basic",
+    process_resolve_env: "process/resolve_env",
+    process_shell_basic: "process/shell/basic",
+    process_spawn_detached: "process/create/detached",
     process_spawn_non_blocking: "process/create/non_blocking",
+    process_spawn_on_exit: "process/create/on_exit",
+    process_spawn_priority: "process/create/priority",
+    process_spawn_signal: "process/create/signal",
     process_spawn_status: "process/create/status",
     process_spawn_stream: "process/create/stream",
+    process_spawn_try_wait: "process/create/try_wait",
 }
 
 #[cfg(feature = "std-regex")]
@@ -265,6 +362,19 @@ create_tests! {
     task_cancel: "task/cancel",
     task_defer: "task/defer",
     task_delay: "task/delay",
+    task_gather: "task/gather",
+    task_parallel_drain: "task/parallel_drain",
+    task_parallel_file: "task/parallel_file",
+    task_parallel_keys: "task/parallel_keys",
+    task_parallel_log: "task/parallel_log",
+    task_parallel_messages: "task/parallel_messages",
+    task_parallel_named: "task/parallel_named",
+    task_parallel_peek: "task/parallel_peek",
+    task_parallel_pop_async: "task/parallel_pop_async",
+    task_select: "task/select",
     task_spawn: "task/spawn",
+    task_thread_pool: "task/thread_pool",
     task_wait: "task/wait",
+    task_wait_cancellable: "task/wait_cancellable",
+    task_worker: "task/worker",
 }